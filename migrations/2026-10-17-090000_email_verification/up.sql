@@ -0,0 +1,2 @@
+-- Time at which the address of an email account was confirmed, NULL until then
+ALTER TABLE accounts ADD COLUMN email_verified TIMESTAMP;
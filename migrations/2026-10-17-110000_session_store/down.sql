@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS sessions_expires_idx;
+DROP INDEX IF EXISTS sessions_user_id_idx;
+
+DELETE FROM sessions WHERE user_id IS NULL;
+ALTER TABLE sessions DROP COLUMN data;
+ALTER TABLE sessions ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE sessions ADD COLUMN session_token TEXT NOT NULL DEFAULT '';
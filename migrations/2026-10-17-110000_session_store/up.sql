@@ -0,0 +1,8 @@
+-- Make the sessions table usable as a tower-sessions store: anonymous sessions have no
+-- user yet, and the session record itself is kept as JSON.
+ALTER TABLE sessions DROP COLUMN session_token;
+ALTER TABLE sessions ALTER COLUMN user_id DROP NOT NULL;
+ALTER TABLE sessions ADD COLUMN data JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+CREATE INDEX sessions_expires_idx ON sessions (expires);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "recovery_codes";
+ALTER TABLE users DROP COLUMN totp_secret;
@@ -0,0 +1,13 @@
+-- Base32 TOTP secret, NULL while two-factor authentication is disabled
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+
+CREATE TABLE recovery_codes
+(
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id),
+	code_hash TEXT NOT NULL,
+	used_at TIMESTAMP,
+	created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
@@ -0,0 +1,10 @@
+-- Failed login counters, keyed by "account:<email>" or "ip:<address>"
+CREATE TABLE login_attempts
+(
+	key TEXT PRIMARY KEY,
+	failures INTEGER NOT NULL,
+	last_failure_at TIMESTAMP NOT NULL,
+	locked_until TIMESTAMP
+);
+
+CREATE INDEX login_attempts_last_failure_at_idx ON login_attempts (last_failure_at);
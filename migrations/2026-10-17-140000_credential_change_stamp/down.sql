@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER accounts_credential_delete ON accounts;
+DROP TRIGGER accounts_credential_change ON accounts;
+DROP FUNCTION rotate_security_stamp_on_credential_change();
+ALTER TABLE users DROP COLUMN security_stamp;
@@ -0,0 +1,24 @@
+-- Random value mixed into the session auth hash; rotating it logs the user out everywhere
+ALTER TABLE users ADD COLUMN security_stamp TEXT NOT NULL DEFAULT gen_random_uuid()::text;
+
+-- Rotate the security stamp whenever the credentials of an account change, so that every
+-- existing session stops validating even when the change does not go through the app
+CREATE FUNCTION rotate_security_stamp_on_credential_change() RETURNS trigger AS $$
+BEGIN
+    UPDATE users
+    SET security_stamp = gen_random_uuid()::text
+    WHERE id = OLD.user_id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER accounts_credential_change
+AFTER UPDATE OF password, email ON accounts
+FOR EACH ROW
+WHEN (OLD.password IS DISTINCT FROM NEW.password OR OLD.email IS DISTINCT FROM NEW.email)
+EXECUTE FUNCTION rotate_security_stamp_on_credential_change();
+
+CREATE TRIGGER accounts_credential_delete
+AFTER DELETE ON accounts
+FOR EACH ROW
+EXECUTE FUNCTION rotate_security_stamp_on_credential_change();
@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE recovery_codes DROP CONSTRAINT recovery_codes_user_id_fkey;
+ALTER TABLE recovery_codes ADD CONSTRAINT recovery_codes_user_id_fkey
+	FOREIGN KEY (user_id) REFERENCES users(id);
+
+ALTER TABLE sessions DROP CONSTRAINT sessions_user_id_fkey;
+ALTER TABLE sessions ADD CONSTRAINT sessions_user_id_fkey
+	FOREIGN KEY (user_id) REFERENCES users(id);
+
+ALTER TABLE accounts DROP CONSTRAINT accounts_user_id_fkey;
+ALTER TABLE accounts ADD CONSTRAINT accounts_user_id_fkey
+	FOREIGN KEY (user_id) REFERENCES users(id);
+
+ALTER TABLE posts DROP CONSTRAINT posts_author_id_fkey;
+ALTER TABLE posts ADD CONSTRAINT posts_author_id_fkey
+	FOREIGN KEY (author_id) REFERENCES users(id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX accounts_provider_account_idx;
+DROP INDEX accounts_email_lower_idx;
+DROP INDEX users_username_lower_idx;
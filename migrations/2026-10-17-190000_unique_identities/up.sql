@@ -0,0 +1,8 @@
+-- Emails are stored normalized (trimmed, lowercased) from now on.
+UPDATE accounts SET email = lower(trim(email)) WHERE email IS NOT NULL;
+
+-- Usernames and emails are unique regardless of case. Creating these fails if existing rows
+-- collide; resolve the duplicates by hand before running the migration.
+CREATE UNIQUE INDEX users_username_lower_idx ON users (lower(username));
+CREATE UNIQUE INDEX accounts_email_lower_idx ON accounts (lower(email)) WHERE type_ = 'email';
+CREATE UNIQUE INDEX accounts_provider_account_idx ON accounts (provider, provider_account_id) WHERE type_ = 'oauth';
@@ -0,0 +1,18 @@
+-- Security audit log of authentication events and credential changes.
+-- Rows outlive the user they mention, `user_id` is cleared when the account is deleted.
+CREATE TABLE audit_events
+(
+	id SERIAL PRIMARY KEY,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	event TEXT NOT NULL,
+	outcome TEXT NOT NULL,
+	user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+	subject TEXT,
+	detail TEXT,
+	ip_address TEXT,
+	user_agent TEXT
+);
+
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX audit_events_user_id_idx ON audit_events (user_id);
+CREATE INDEX audit_events_event_idx ON audit_events (event);
@@ -0,0 +1,14 @@
+-- Invite codes for the invite-only signup mode; only the sha256 of the code is stored
+CREATE TABLE invites
+(
+	id SERIAL PRIMARY KEY,
+	code_hash TEXT NOT NULL UNIQUE,
+	inviter_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+	note TEXT NOT NULL DEFAULT '',
+	max_uses INTEGER NOT NULL DEFAULT 1 CHECK (max_uses > 0),
+	uses INTEGER NOT NULL DEFAULT 0 CHECK (uses >= 0 AND uses <= max_uses),
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	expires_at TIMESTAMP
+);
+
+CREATE INDEX invites_inviter_id_idx ON invites (inviter_id);
@@ -0,0 +1,2 @@
+-- The feed pages through published posts newest first, see `Post::get_published`.
+CREATE INDEX posts_published_feed_idx ON posts (created_at DESC, id DESC) WHERE published;
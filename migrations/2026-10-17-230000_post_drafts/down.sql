@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS posts_author_drafts_idx;
+DROP INDEX IF EXISTS posts_publish_at_idx;
+ALTER TABLE posts DROP COLUMN hidden;
+ALTER TABLE posts DROP COLUMN publish_at;
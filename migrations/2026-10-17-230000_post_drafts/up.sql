@@ -0,0 +1,8 @@
+-- Drafts are unpublished posts. A draft with `publish_at` is published by a background
+-- task once that time has passed.
+ALTER TABLE posts ADD COLUMN publish_at TIMESTAMP;
+-- Set when a moderator hides a post, so that its author cannot publish it again
+ALTER TABLE posts ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX posts_publish_at_idx ON posts (publish_at) WHERE NOT published AND publish_at IS NOT NULL;
+CREATE INDEX posts_author_drafts_idx ON posts (author_id) WHERE NOT published;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE posts DROP COLUMN body_html_revision;
+ALTER TABLE posts DROP COLUMN body_html;
+ALTER TABLE posts DROP COLUMN revision;
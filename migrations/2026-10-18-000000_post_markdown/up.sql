@@ -0,0 +1,5 @@
+-- Bodies are Markdown. `revision` goes up on every edit; `body_html` caches the sanitized
+-- rendering of the body at `body_html_revision` and is stale once the two differ.
+ALTER TABLE posts ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE posts ADD COLUMN body_html TEXT;
+ALTER TABLE posts ADD COLUMN body_html_revision INTEGER;
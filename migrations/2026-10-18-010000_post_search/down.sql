@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS posts_search_idx;
+ALTER TABLE posts DROP COLUMN search_vector;
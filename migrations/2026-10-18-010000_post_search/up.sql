@@ -0,0 +1,6 @@
+-- Full-text search over posts, see `Post::search`. Titles weigh more than bodies.
+ALTER TABLE posts ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+	setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', body), 'B')
+) STORED;
+
+CREATE INDEX posts_search_idx ON posts USING GIN (search_vector);
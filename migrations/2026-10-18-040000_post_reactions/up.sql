@@ -0,0 +1,11 @@
+-- One row per user and kind of reaction, see `PostReaction::KINDS`
+CREATE TABLE post_reactions
+(
+	post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	reaction TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	PRIMARY KEY (post_id, user_id, reaction)
+);
+
+CREATE INDEX post_reactions_user_id_idx ON post_reactions (user_id);
@@ -0,0 +1,6 @@
+-- A deleted comment that has replies stays as a tombstone so that the replies keep
+-- their place, and deleting a comment no longer takes other users' replies with it
+ALTER TABLE comments ADD COLUMN deleted_at TIMESTAMP;
+ALTER TABLE comments DROP CONSTRAINT comments_parent_id_fkey;
+ALTER TABLE comments ADD CONSTRAINT comments_parent_id_fkey
+	FOREIGN KEY (parent_id) REFERENCES comments(id);
@@ -0,0 +1,83 @@
+//! Authentication of API requests with personal access tokens.
+use crate::db::models::api_tokens::ApiToken;
+use crate::db::models::users::{Backend, User};
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::{StatusCode, header, request::Parts};
+use axum::response::{IntoResponse, Response};
+use tokio::task;
+
+/// The user behind an `Authorization: Bearer <token>` request, with the token's scopes.
+///
+/// `user` is the same type `AuthSession` yields, so API handlers can share code with the
+/// HTML ones.
+#[derive(Debug, Clone)]
+pub struct ApiUser {
+    pub user: User,
+    pub token: ApiToken,
+}
+
+impl ApiUser {
+    /// Rejects the request with 403 unless the token was given `scope`.
+    pub fn require_scope(&self, scope: &str) -> Result<(), ApiAuthError> {
+        if self.token.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(ApiAuthError::MissingScope)
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiAuthError {
+    #[error("missing or invalid bearer token")]
+    InvalidToken,
+    #[error("token lacks the required scope")]
+    MissingScope,
+    #[error("failed to check the token")]
+    Internal,
+}
+
+impl IntoResponse for ApiAuthError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiAuthError::InvalidToken => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                self.to_string(),
+            )
+                .into_response(),
+            ApiAuthError::MissingScope => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+            ApiAuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for ApiUser
+where
+    Backend: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiAuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string())
+            .filter(|token| token.starts_with(ApiToken::PREFIX))
+            .ok_or(ApiAuthError::InvalidToken)?;
+
+        let db = Backend::from_ref(state).db;
+        let found = task::spawn_blocking(move || {
+            let mut conn = db.get().map_err(|_| ApiAuthError::Internal)?;
+            ApiToken::authenticate(&mut conn, &token).map_err(|_| ApiAuthError::Internal)
+        })
+        .await
+        .map_err(|_| ApiAuthError::Internal)??;
+
+        let (user, token) = found.ok_or(ApiAuthError::InvalidToken)?;
+        Ok(ApiUser { user, token })
+    }
+}
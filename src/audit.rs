@@ -0,0 +1,83 @@
+//! Recording of security audit events from handlers.
+use crate::client_info::{ClientIp, UserAgent};
+use crate::db::models::audit_events::{AuditEvent, NewAuditEvent};
+use crate::db::models::users::Backend;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use tokio::task;
+
+/// Records audit events for the current request, tagged with the client address and
+/// user agent.
+///
+/// Recording never fails the request: a database error is logged and the event is lost.
+#[derive(Clone)]
+pub struct Audit {
+    backend: Backend,
+    ip: String,
+    user_agent: String,
+}
+
+/// What to record, see the constants of [`AuditEvent`].
+#[derive(Debug, Clone, Default)]
+pub struct Entry<'a> {
+    pub event: &'a str,
+    pub outcome: &'a str,
+    pub user_id: Option<i32>,
+    pub subject: Option<&'a str>,
+    pub detail: Option<&'a str>,
+}
+
+impl Audit {
+    pub async fn record(&self, entry: Entry<'_>) {
+        let new_event = NewAuditEvent {
+            event: entry.event.to_string(),
+            outcome: entry.outcome.to_string(),
+            user_id: entry.user_id,
+            subject: entry.subject.map(str::to_string),
+            detail: entry.detail.map(str::to_string),
+            ip_address: Some(self.ip.clone()),
+            user_agent: Some(self.user_agent.clone()).filter(|ua| !ua.is_empty()),
+        };
+        let db = self.backend.db.clone();
+        let result = task::spawn_blocking(move || {
+            let mut conn = db.get().map_err(|e| e.to_string())?;
+            AuditEvent::record(&mut conn, &new_event).map_err(|e| e.to_string())
+        })
+        .await;
+        match result {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("Failed to record audit event: {e}"),
+            Err(e) => eprintln!("Failed to record audit event: {e}"),
+        }
+    }
+
+    /// Records a successful `event` of `user_id`
+    pub async fn success(&self, event: &str, user_id: i32) {
+        self.record(Entry {
+            event,
+            outcome: AuditEvent::SUCCESS,
+            user_id: Some(user_id),
+            ..Entry::default()
+        })
+        .await;
+    }
+}
+
+impl<S> FromRequestParts<S> for Audit
+where
+    Backend: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let UserAgent(user_agent) = UserAgent::from_request_parts(parts, state).await?;
+        Ok(Audit {
+            backend: Backend::from_ref(state),
+            ip: ip.to_string(),
+            user_agent,
+        })
+    }
+}
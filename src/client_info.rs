@@ -0,0 +1,137 @@
+//! Information about the client sending a request.
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use once_cell::sync::Lazy;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// How many reverse proxies in front of the app append to `X-Forwarded-For`.
+///
+/// `TRUST_PROXY_HEADERS=true` means one proxy; a number gives the count for chains of
+/// proxies. Anything else leaves the header alone.
+static TRUSTED_PROXY_HOPS: Lazy<usize> =
+    Lazy::new(|| match std::env::var("TRUST_PROXY_HEADERS").as_deref() {
+        Ok("true") => 1,
+        Ok(hops) => hops.parse().unwrap_or(0),
+        Err(_) => 0,
+    });
+
+/// The client address recorded by the outermost of `hops` trusted proxies.
+///
+/// Each proxy appends the address it received the request from, so only the last `hops`
+/// entries were written by trusted proxies; anything to their left comes from the client
+/// and can be forged.
+fn forwarded_client<'a>(values: impl Iterator<Item = &'a str>, hops: usize) -> Option<IpAddr> {
+    if hops == 0 {
+        return None;
+    }
+    let entries: Vec<&str> = values.flat_map(|value| value.split(',')).collect();
+    entries
+        .len()
+        .checked_sub(hops)
+        .and_then(|index| entries[index].trim().parse().ok())
+}
+
+/// Address of the client, taken from the connection or from the proxy headers when trusted.
+///
+/// Requires the app to be served with `into_make_service_with_connect_info::<SocketAddr>()`.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let forwarded = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok());
+        if let Some(ip) = forwarded_client(forwarded, *TRUSTED_PROXY_HOPS) {
+            return Ok(ClientIp(ip));
+        }
+
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        Ok(ClientIp(ip))
+    }
+}
+
+/// Raw `User-Agent` header of the request, empty when missing.
+#[derive(Debug, Clone, Default)]
+pub struct UserAgent(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for UserAgent {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(axum::http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        Ok(UserAgent(user_agent.to_string()))
+    }
+}
+
+/// Short human readable description of a user agent, such as "Firefox on Linux".
+///
+/// Only recognizes the common browsers and systems; anything else is reported as unknown
+/// rather than guessed.
+pub fn describe_user_agent(user_agent: &str) -> String {
+    // Order matters: Edge and Opera also claim to be Chrome, and Chrome claims to be Safari.
+    const BROWSERS: [(&str, &str); 7] = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+        ("python-requests/", "Python"),
+    ];
+    const SYSTEMS: [(&str, &str); 7] = [
+        ("Windows", "Windows"),
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ];
+
+    let find = |table: &[(&str, &'static str)]| {
+        table
+            .iter()
+            .find(|(needle, _)| user_agent.contains(needle))
+            .map(|(_, name)| *name)
+    };
+    match (find(&BROWSERS), find(&SYSTEMS)) {
+        (Some(browser), Some(system)) => format!("{browser} on {system}"),
+        (Some(browser), None) => browser.to_string(),
+        (None, Some(system)) => format!("Unknown browser on {system}"),
+        (None, None) => "Unknown device".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_client_skips_entries_added_by_the_client() {
+        let header = ["203.0.113.9, 198.51.100.7, 10.0.0.2"];
+        let ip = |s: &str| s.parse::<IpAddr>().ok();
+
+        assert_eq!(forwarded_client(header.into_iter(), 0), None);
+        assert_eq!(forwarded_client(header.into_iter(), 1), ip("10.0.0.2"));
+        assert_eq!(forwarded_client(header.into_iter(), 2), ip("198.51.100.7"));
+        assert_eq!(forwarded_client(header.into_iter(), 4), None);
+        // Repeated headers are read as one list.
+        let split = ["203.0.113.9", "198.51.100.7"];
+        assert_eq!(forwarded_client(split.into_iter(), 1), ip("198.51.100.7"));
+        assert_eq!(forwarded_client(["forged"].into_iter(), 1), None);
+    }
+}
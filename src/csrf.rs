@@ -0,0 +1,151 @@
+//! Protection against cross-site request forgery for the cookie-authenticated routes.
+//!
+//! Each session holds a random token. Pages put it on their `<body>` as
+//! `hx-headers='{"X-CSRF-Token": "..."}'`, so every htmx request sends it as a header, and
+//! plain HTML forms carry it in a hidden `csrf_token` field. [`verify`] rejects state-changing
+//! requests whose token does not match the session.
+use crate::templates::{CsrfErrorTemplate, ErrorMessageTemplate};
+use askama::Template;
+use axum::body::{Body, to_bytes};
+use axum::extract::{FromRequestParts, Request};
+use axum::http::{HeaderMap, Method, StatusCode, header, request::Parts};
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse, Response};
+use axum_login::tower_sessions::Session;
+
+/// Session key of the token
+pub const SESSION_KEY: &str = "csrf.token";
+/// Header sent by htmx requests, see `hx-headers` on the page bodies
+pub const HEADER: &str = "x-csrf-token";
+/// Form field of plain HTML forms
+pub const FORM_FIELD: &str = "csrf_token";
+/// Largest form body read while looking for the token field
+const MAX_FORM_BYTES: usize = 1024 * 1024;
+
+/// The CSRF token of the current session, created on first use.
+///
+/// Handlers rendering a page with forms take this extractor and hand the token to the
+/// template.
+#[derive(Debug, Clone)]
+pub struct CsrfToken(pub String);
+
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        session_token(&session)
+            .await
+            .map(CsrfToken)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/// The token of `session`, storing a new one if it has none yet
+pub async fn session_token(
+    session: &Session,
+) -> Result<String, axum_login::tower_sessions::session::Error> {
+    if let Some(token) = session.get::<String>(SESSION_KEY).await? {
+        return Ok(token);
+    }
+    let token = crate::tokens::generate_token();
+    session.insert(SESSION_KEY, &token).await?;
+    Ok(token)
+}
+
+/// Middleware rejecting state-changing requests without the session's token.
+///
+/// `GET`, `HEAD` and `OPTIONS` requests are let through, as is `/api/`, which is
+/// authenticated by bearer tokens rather than cookies and so cannot be forged cross-site.
+pub async fn verify(session: Session, request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) || request.uri().path().starts_with("/api/")
+    {
+        return next.run(request).await;
+    }
+
+    let expected = match session.get::<String>(SESSION_KEY).await {
+        Ok(expected) => expected,
+        Err(e) => {
+            eprintln!("Failed to read CSRF token: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let htmx = request.headers().contains_key("hx-request");
+
+    let (provided, request) = match header_token(request.headers()) {
+        Some(token) => (Some(token), request),
+        None => match form_token(request).await {
+            Ok(found) => found,
+            Err(response) => return response,
+        },
+    };
+
+    match (expected, provided) {
+        (Some(expected), Some(provided)) if constant_time_eq(&expected, &provided) => {
+            next.run(request).await
+        }
+        _ => reject(htmx),
+    }
+}
+
+fn header_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Looks for the token in a urlencoded form body, handing back a request with the same body.
+async fn form_token(request: Request) -> Result<(Option<String>, Request), Response> {
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Ok((None, request));
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = to_bytes(body, MAX_FORM_BYTES)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes)
+        .ok()
+        .and_then(|fields| {
+            fields
+                .into_iter()
+                .find(|(name, _)| name == FORM_FIELD)
+                .map(|(_, value)| value)
+        });
+    Ok((token, Request::from_parts(parts, Body::from(bytes))))
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// htmx requests get the usual error fragment, swapped like any other handler error;
+/// plain form posts get a full error page.
+fn reject(htmx: bool) -> Response {
+    if htmx {
+        let template = ErrorMessageTemplate {
+            message: "This form has expired or was sent from another site. Reload the page and try again.".to_string(),
+        };
+        return Html(template.render().unwrap()).into_response();
+    }
+    let template = CsrfErrorTemplate {};
+    (StatusCode::FORBIDDEN, Html(template.render().unwrap())).into_response()
+}
@@ -15,3 +15,14 @@ pub fn establish_pool() -> PgPool {
         .build(manager)
         .expect("Failed to create pool.")
 }
+
+diesel::define_sql_function! {
+    /// SQL `lower()`, matching the case-insensitive unique indexes on usernames and emails
+    fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text;
+}
+
+/// SQL for a new `users.security_stamp`, the same expression as the column default and the
+/// credential change trigger use
+pub fn new_security_stamp() -> diesel::expression::SqlLiteral<diesel::sql_types::Text> {
+    diesel::dsl::sql("gen_random_uuid()::text")
+}
@@ -1,3 +1,6 @@
 pub mod db_utils;
 pub mod models;
 pub mod schema;
+pub mod session_store;
+#[cfg(test)]
+pub(crate) mod test_db;
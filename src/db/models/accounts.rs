@@ -1,4 +1,6 @@
+use crate::db::db_utils::lower;
 use crate::db::schema::accounts;
+use crate::validation::normalize_email;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -6,7 +8,7 @@ use serde::{Deserialize, Serialize};
 ///
 /// The `type_` field determines the authentication method:
 /// - "email" for email/password authentication
-/// - "oauth" for OAuth providers (TODO: implement OAuth support)
+/// - "oauth" for OAuth2 / OpenID Connect providers
 ///
 /// For email authentication: email and password fields are used
 /// For OAuth authentication: provider, provider_account_id, and token fields are used
@@ -16,14 +18,14 @@ use serde::{Deserialize, Serialize};
 pub struct Account {
     pub id: i32,
     pub user_id: i32,
-    /// Authentication type: "email" for email/password, "oauth" for OAuth (TODO)
+    /// Authentication type: "email" for email/password, "oauth" for OAuth
     pub type_: String,
 
     // Email/password authentication fields
     pub email: Option<String>,
     pub password: Option<String>,
 
-    // OAuth authentication fields (TODO: implement OAuth support)
+    // OAuth authentication fields
     pub provider: Option<String>,
     pub provider_account_id: Option<String>,
     pub refresh_token: Option<String>,
@@ -34,6 +36,9 @@ pub struct Account {
     pub id_token: Option<String>,
     pub session_state: Option<String>,
     pub refresh_token_expires_in: Option<i32>,
+
+    /// When the email address was confirmed, `None` while unverified
+    pub email_verified: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -47,8 +52,26 @@ pub struct NewAccount {
     pub provider_account_id: Option<String>,
 }
 
+/// Token material returned by an OAuth provider, written on link and on every sign-in.
+///
+/// `None` fields are left untouched on update so that a provider which does not rotate
+/// its refresh token keeps the one issued earlier.
+#[derive(AsChangeset, Debug, Clone, Default)]
+#[diesel(table_name = accounts)]
+pub struct OAuthTokens {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<i32>,
+    pub token_type: Option<String>,
+    pub scope: Option<String>,
+    pub id_token: Option<String>,
+}
+
 impl Account {
-    /// Create a new email/password account
+    /// Name of the unique index on lowercased email addresses of email accounts
+    pub const EMAIL_UNIQUE_INDEX: &'static str = "accounts_email_lower_idx";
+
+    /// Create a new email/password account, storing the normalized address
     pub fn create_email_account(
         conn: &mut diesel::PgConnection,
         user_id: i32,
@@ -58,7 +81,7 @@ impl Account {
         let new_account = NewAccount {
             user_id,
             type_: "email".to_string(),
-            email: Some(email),
+            email: Some(normalize_email(&email)),
             password: Some(password_hash),
             provider: None,
             provider_account_id: None,
@@ -70,15 +93,108 @@ impl Account {
             .get_result(conn)
     }
 
-    /// Find account by email for authentication
+    /// Find account by email for authentication, ignoring case
     pub fn find_by_email(
         conn: &mut diesel::PgConnection,
         email: &str,
     ) -> Result<Account, diesel::result::Error> {
         accounts::table
-            .filter(accounts::email.eq(email))
+            .filter(lower(accounts::email.assume_not_null()).eq(normalize_email(email)))
             .filter(accounts::type_.eq("email"))
             .select(Account::as_select())
             .first(conn)
     }
+
+    /// Mark the email account for `email` as verified
+    pub fn mark_email_verified(
+        conn: &mut diesel::PgConnection,
+        email: &str,
+    ) -> Result<Account, diesel::result::Error> {
+        diesel::update(
+            accounts::table
+                .filter(lower(accounts::email.assume_not_null()).eq(normalize_email(email)))
+                .filter(accounts::type_.eq("email")),
+        )
+        .set(accounts::email_verified.eq(chrono::Utc::now().naive_utc()))
+        .returning(Account::as_returning())
+        .get_result(conn)
+    }
+
+    /// Find the email/password account of a user, if they have one
+    pub fn find_email_account_by_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<Option<Account>, diesel::result::Error> {
+        accounts::table
+            .filter(accounts::user_id.eq(user_id))
+            .filter(accounts::type_.eq("email"))
+            .select(Account::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Replace the password hash of an email account
+    pub fn update_password(
+        conn: &mut diesel::PgConnection,
+        account_id: i32,
+        password_hash: String,
+    ) -> Result<Account, diesel::result::Error> {
+        diesel::update(accounts::table.find(account_id))
+            .set(accounts::password.eq(password_hash))
+            .returning(Account::as_returning())
+            .get_result(conn)
+    }
+
+    /// Find the OAuth account linked to a provider identity
+    pub fn find_by_provider(
+        conn: &mut diesel::PgConnection,
+        provider: &str,
+        provider_account_id: &str,
+    ) -> Result<Option<Account>, diesel::result::Error> {
+        accounts::table
+            .filter(accounts::type_.eq("oauth"))
+            .filter(accounts::provider.eq(provider))
+            .filter(accounts::provider_account_id.eq(provider_account_id))
+            .select(Account::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Create a new OAuth account linked to `user_id`
+    pub fn create_oauth_account(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        provider: String,
+        provider_account_id: String,
+        tokens: &OAuthTokens,
+    ) -> Result<Account, diesel::result::Error> {
+        let new_account = NewAccount {
+            user_id,
+            type_: "oauth".to_string(),
+            email: None,
+            password: None,
+            provider: Some(provider),
+            provider_account_id: Some(provider_account_id),
+        };
+
+        conn.transaction(|conn| {
+            let account = diesel::insert_into(accounts::table)
+                .values(&new_account)
+                .returning(Account::as_returning())
+                .get_result::<Account>(conn)?;
+            Account::update_oauth_tokens(conn, account.id, tokens)
+        })
+    }
+
+    /// Store the latest tokens issued by the provider
+    pub fn update_oauth_tokens(
+        conn: &mut diesel::PgConnection,
+        account_id: i32,
+        tokens: &OAuthTokens,
+    ) -> Result<Account, diesel::result::Error> {
+        diesel::update(accounts::table.find(account_id))
+            .set(tokens)
+            .returning(Account::as_returning())
+            .get_result(conn)
+    }
 }
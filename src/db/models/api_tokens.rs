@@ -0,0 +1,119 @@
+use super::users::User;
+use crate::db::schema::{api_tokens, users};
+use crate::tokens::{generate_token, hash_token};
+use diesel::prelude::*;
+
+/// Personal access token authenticating `Authorization: Bearer` API requests.
+/// Only the hash of the token is stored; the plain token is shown once on creation.
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = api_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ApiToken {
+    /// Prefix of every token, making leaked tokens easy to recognize
+    pub const PREFIX: &'static str = "z3_";
+    /// Allows reading posts through the API
+    pub const SCOPE_POSTS_READ: &'static str = "posts:read";
+    /// Allows creating posts through the API
+    pub const SCOPE_POSTS_WRITE: &'static str = "posts:write";
+    /// `(scope, description)` of every scope a token can be given
+    pub const SCOPES: [(&'static str, &'static str); 2] = [
+        (Self::SCOPE_POSTS_READ, "Read posts"),
+        (Self::SCOPE_POSTS_WRITE, "Create posts"),
+    ];
+
+    /// Create a token for `user_id`, returning it along with the plain token
+    pub fn create(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        name: &str,
+        scopes: Vec<String>,
+        expires_at: Option<chrono::NaiveDateTime>,
+    ) -> Result<(ApiToken, String), diesel::result::Error> {
+        let token = format!("{}{}", Self::PREFIX, generate_token());
+        let created = diesel::insert_into(api_tokens::table)
+            .values((
+                api_tokens::user_id.eq(user_id),
+                api_tokens::name.eq(name),
+                api_tokens::token_hash.eq(hash_token(&token)),
+                api_tokens::scopes.eq(scopes),
+                api_tokens::created_at.eq(chrono::Utc::now().naive_utc()),
+                api_tokens::expires_at.eq(expires_at),
+            ))
+            .returning(ApiToken::as_returning())
+            .get_result(conn)?;
+        Ok((created, token))
+    }
+
+    /// Tokens of `user_id`, newest first
+    pub fn list_for_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<ApiToken>, diesel::result::Error> {
+        api_tokens::table
+            .filter(api_tokens::user_id.eq(user_id))
+            .order(api_tokens::created_at.desc())
+            .select(ApiToken::as_select())
+            .load(conn)
+    }
+
+    /// Delete a token, scoped to its owner so users cannot revoke each other's tokens
+    pub fn revoke(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        token_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(
+            api_tokens::table
+                .filter(api_tokens::id.eq(token_id))
+                .filter(api_tokens::user_id.eq(user_id)),
+        )
+        .execute(conn)
+    }
+
+    /// Resolve a plain token to its owner, recording the use.
+    /// Unknown and expired tokens yield `None`.
+    pub fn authenticate(
+        conn: &mut diesel::PgConnection,
+        token: &str,
+    ) -> Result<Option<(User, ApiToken)>, diesel::result::Error> {
+        let now = chrono::Utc::now().naive_utc();
+        let found = api_tokens::table
+            .inner_join(users::table)
+            .filter(api_tokens::token_hash.eq(hash_token(token)))
+            .filter(
+                api_tokens::expires_at
+                    .is_null()
+                    .or(api_tokens::expires_at.gt(now)),
+            )
+            .select((User::as_select(), ApiToken::as_select()))
+            .first::<(User, ApiToken)>(conn)
+            .optional()?;
+
+        if let Some((_, api_token)) = &found {
+            diesel::update(api_tokens::table.find(api_token.id))
+                .set(api_tokens::last_used_at.eq(now))
+                .execute(conn)?;
+        }
+        Ok(found)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= chrono::Utc::now().naive_utc())
+    }
+}
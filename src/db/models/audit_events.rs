@@ -0,0 +1,161 @@
+use crate::db::db_utils::lower;
+use crate::db::schema::{audit_events, users};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A recorded authentication event or credential change, see [`crate::audit::Audit`].
+#[derive(Queryable, Selectable, Clone, Debug, Serialize)]
+#[diesel(table_name = audit_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditEvent {
+    pub id: i32,
+    pub created_at: chrono::NaiveDateTime,
+    /// What happened, one of the `AuditEvent` constants
+    pub event: String,
+    /// `success`, `failure` or `blocked`
+    pub outcome: String,
+    /// User acting or, for failed logins, the account targeted when it is known
+    pub user_id: Option<i32>,
+    /// What the event is about in the user's words, such as the email address typed in
+    pub subject: Option<String>,
+    /// Free-form context such as the sign-in method or the reason of a failure
+    pub detail: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = audit_events)]
+pub struct NewAuditEvent {
+    pub event: String,
+    pub outcome: String,
+    pub user_id: Option<i32>,
+    pub subject: Option<String>,
+    pub detail: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// An event with the current username of its user, as listed on the admin page.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub username: Option<String>,
+}
+
+/// Filters of the admin audit log, empty fields match everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+    pub event: Option<String>,
+    pub outcome: Option<String>,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    /// Only events older than this id, for paging
+    pub before: Option<i32>,
+}
+
+impl AuditFilter {
+    fn non_empty(value: &Option<String>) -> Option<&str> {
+        value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+    }
+}
+
+impl AuditEvent {
+    pub const LOGIN: &'static str = "login";
+    pub const LOGIN_TWO_FACTOR: &'static str = "login.2fa";
+    pub const SIGNUP: &'static str = "signup";
+    pub const SIGNOUT: &'static str = "signout";
+    pub const SIGNOUT_EVERYWHERE: &'static str = "signout.everywhere";
+    pub const PASSWORD_RESET: &'static str = "password.reset";
+    pub const TWO_FACTOR_ENABLE: &'static str = "2fa.enable";
+    pub const TWO_FACTOR_DISABLE: &'static str = "2fa.disable";
+    pub const API_TOKEN_CREATE: &'static str = "api_token.create";
+    pub const API_TOKEN_REVOKE: &'static str = "api_token.revoke";
+    pub const SESSION_REVOKE: &'static str = "session.revoke";
+    pub const ACCOUNT_DELETE: &'static str = "account.delete";
+    pub const ROLE_CHANGE: &'static str = "role.change";
+    pub const INVITE_CREATE: &'static str = "invite.create";
+    pub const INVITE_REVOKE: &'static str = "invite.revoke";
+    /// Every event name, offered by the filter of the admin page
+    pub const EVENTS: [&'static str; 15] = [
+        Self::LOGIN,
+        Self::LOGIN_TWO_FACTOR,
+        Self::SIGNUP,
+        Self::SIGNOUT,
+        Self::SIGNOUT_EVERYWHERE,
+        Self::PASSWORD_RESET,
+        Self::TWO_FACTOR_ENABLE,
+        Self::TWO_FACTOR_DISABLE,
+        Self::API_TOKEN_CREATE,
+        Self::API_TOKEN_REVOKE,
+        Self::SESSION_REVOKE,
+        Self::ACCOUNT_DELETE,
+        Self::ROLE_CHANGE,
+        Self::INVITE_CREATE,
+        Self::INVITE_REVOKE,
+    ];
+
+    pub const SUCCESS: &'static str = "success";
+    pub const FAILURE: &'static str = "failure";
+    /// Refused before checking credentials, e.g. by the login throttle
+    pub const BLOCKED: &'static str = "blocked";
+    pub const OUTCOMES: [&'static str; 3] = [Self::SUCCESS, Self::FAILURE, Self::BLOCKED];
+
+    pub fn record(
+        conn: &mut diesel::PgConnection,
+        new_event: &NewAuditEvent,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(audit_events::table)
+            .values(new_event)
+            .execute(conn)
+    }
+
+    /// Events matching `filter`, newest first
+    pub fn search(
+        conn: &mut diesel::PgConnection,
+        filter: &AuditFilter,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, diesel::result::Error> {
+        let mut query = audit_events::table
+            .left_join(users::table)
+            .select((AuditEvent::as_select(), users::username.nullable()))
+            .order(audit_events::id.desc())
+            .limit(limit)
+            .into_boxed();
+
+        if let Some(event) = AuditFilter::non_empty(&filter.event) {
+            query = query.filter(audit_events::event.eq(event.to_string()));
+        }
+        if let Some(outcome) = AuditFilter::non_empty(&filter.outcome) {
+            query = query.filter(audit_events::outcome.eq(outcome.to_string()));
+        }
+        if let Some(username) = AuditFilter::non_empty(&filter.username) {
+            query = query.filter(lower(users::username).eq(username.to_lowercase()));
+        }
+        if let Some(ip) = AuditFilter::non_empty(&filter.ip) {
+            query = query.filter(audit_events::ip_address.eq(ip.to_string()));
+        }
+        if let Some(before) = filter.before {
+            query = query.filter(audit_events::id.lt(before));
+        }
+
+        let rows: Vec<(AuditEvent, Option<String>)> = query.load(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(|(event, username)| AuditEntry { event, username })
+            .collect())
+    }
+
+    /// Events of `user_id`, newest first, for the personal data export
+    pub fn list_for_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<AuditEvent>, diesel::result::Error> {
+        audit_events::table
+            .filter(audit_events::user_id.eq(user_id))
+            .order(audit_events::id.desc())
+            .select(AuditEvent::as_select())
+            .load(conn)
+    }
+}
@@ -0,0 +1,284 @@
+use crate::db::schema::{comments, users};
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A comment on a post, or a reply to another comment of the same post.
+#[derive(Queryable, Selectable, Clone, Debug, Serialize)]
+#[diesel(table_name = comments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Comment {
+    pub id: i32,
+    pub post_id: i32,
+    /// Comment replied to, `None` for top-level comments
+    pub parent_id: Option<i32>,
+    /// `None` once the author deleted their account or the comment
+    pub author_id: Option<i32>,
+    /// Markdown, like post bodies; empty once deleted
+    pub body: String,
+    /// 0 for top-level comments, one more than the parent for replies
+    pub depth: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub edited_at: Option<chrono::NaiveDateTime>,
+    /// Set when the comment was deleted while it had replies
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+}
+
+/// What [`Comment::delete`] did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommentDeletion {
+    /// The comment has replies and was kept as a tombstone
+    Tombstoned,
+    /// The comment was removed, along with tombstones it was the last reply of.
+    /// `outermost_id` is the highest comment removed from the thread.
+    Removed { outermost_id: i32 },
+}
+
+/// A comment with the username of its author, as shown under a post.
+#[derive(Clone, Debug)]
+pub struct CommentListing {
+    pub comment: Comment,
+    pub author: Option<String>,
+}
+
+/// A comment with its replies, oldest first.
+#[derive(Clone, Debug)]
+pub struct CommentNode {
+    pub listing: CommentListing,
+    pub replies: Vec<CommentNode>,
+}
+
+impl Comment {
+    /// Add a comment to `post_id`, as a reply to `parent` when given.
+    ///
+    /// The parent must belong to the same post; callers check the depth limit.
+    pub fn create(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+        parent: Option<&Comment>,
+        author_id: i32,
+        body: &str,
+    ) -> Result<Comment, diesel::result::Error> {
+        diesel::insert_into(comments::table)
+            .values((
+                comments::post_id.eq(post_id),
+                comments::parent_id.eq(parent.map(|parent| parent.id)),
+                comments::author_id.eq(author_id),
+                comments::body.eq(body),
+                comments::depth.eq(parent.map_or(0, |parent| parent.depth + 1)),
+                comments::created_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .returning(Comment::as_returning())
+            .get_result(conn)
+    }
+
+    pub fn find(
+        conn: &mut diesel::PgConnection,
+        comment_id: i32,
+    ) -> Result<Option<Comment>, diesel::result::Error> {
+        comments::table
+            .find(comment_id)
+            .select(Comment::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// One comment with its author's username
+    pub fn find_listing(
+        conn: &mut diesel::PgConnection,
+        comment_id: i32,
+    ) -> Result<Option<CommentListing>, diesel::result::Error> {
+        let row: Option<(Comment, Option<String>)> = comments::table
+            .left_join(users::table)
+            .filter(comments::id.eq(comment_id))
+            .select((Comment::as_select(), users::username.nullable()))
+            .first(conn)
+            .optional()?;
+        Ok(row.map(|(comment, author)| CommentListing { comment, author }))
+    }
+
+    /// Every comment of `post_id` with its author, arranged in threads, oldest first
+    pub fn thread_for_post(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+    ) -> Result<Vec<CommentNode>, diesel::result::Error> {
+        let rows: Vec<(Comment, Option<String>)> = comments::table
+            .left_join(users::table)
+            .filter(comments::post_id.eq(post_id))
+            .order((comments::created_at.asc(), comments::id.asc()))
+            .select((Comment::as_select(), users::username.nullable()))
+            .load(conn)?;
+        let listings = rows
+            .into_iter()
+            .map(|(comment, author)| CommentListing { comment, author })
+            .collect();
+        Ok(CommentNode::build(listings))
+    }
+
+    /// Replace the body, marking the comment as edited
+    pub fn update(
+        conn: &mut diesel::PgConnection,
+        comment_id: i32,
+        body: &str,
+    ) -> Result<Comment, diesel::result::Error> {
+        diesel::update(comments::table.find(comment_id))
+            .set((
+                comments::body.eq(body),
+                comments::edited_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .returning(Comment::as_returning())
+            .get_result(conn)
+    }
+
+    /// Delete a comment without touching its replies.
+    ///
+    /// A comment with replies is kept as a tombstone, without author or body, so that the
+    /// replies keep their place. Tombstones left without replies are removed as well.
+    pub fn delete(
+        conn: &mut diesel::PgConnection,
+        comment_id: i32,
+    ) -> Result<CommentDeletion, diesel::result::Error> {
+        conn.transaction(|conn| {
+            if Comment::has_replies(conn, comment_id)? {
+                diesel::update(comments::table.find(comment_id))
+                    .set((
+                        comments::body.eq(""),
+                        comments::author_id.eq(None::<i32>),
+                        comments::deleted_at.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)?;
+                return Ok(CommentDeletion::Tombstoned);
+            }
+
+            let mut outermost_id = comment_id;
+            let mut parent_id: Option<i32> = diesel::delete(comments::table.find(comment_id))
+                .returning(comments::parent_id)
+                .get_result(conn)?;
+            while let Some(id) = parent_id {
+                let is_tombstone = Comment::find(conn, id)?.is_some_and(|c| c.is_deleted());
+                if !is_tombstone || Comment::has_replies(conn, id)? {
+                    break;
+                }
+                parent_id = diesel::delete(comments::table.find(id))
+                    .returning(comments::parent_id)
+                    .get_result(conn)?;
+                outermost_id = id;
+            }
+            Ok(CommentDeletion::Removed { outermost_id })
+        })
+    }
+
+    fn has_replies(
+        conn: &mut diesel::PgConnection,
+        comment_id: i32,
+    ) -> Result<bool, diesel::result::Error> {
+        diesel::select(diesel::dsl::exists(
+            comments::table.filter(comments::parent_id.eq(comment_id)),
+        ))
+        .get_result(conn)
+    }
+
+    /// Comments of `author_id`, oldest first, for the personal data export
+    pub fn list_for_author(
+        conn: &mut diesel::PgConnection,
+        author_id: i32,
+    ) -> Result<Vec<Comment>, diesel::result::Error> {
+        comments::table
+            .filter(comments::author_id.eq(author_id))
+            .order(comments::created_at)
+            .select(Comment::as_select())
+            .load(conn)
+    }
+
+    pub fn is_authored_by(&self, user_id: i32) -> bool {
+        self.author_id == Some(user_id)
+    }
+
+    /// Whether this is the tombstone of a deleted comment
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Whether the comment can be replied to: it is not deleted, and replies stay within
+    /// [`crate::validation::COMMENT_MAX_DEPTH`]
+    pub fn accepts_replies(&self) -> bool {
+        !self.is_deleted() && self.depth + 1 < crate::validation::COMMENT_MAX_DEPTH
+    }
+
+    /// The body rendered to sanitized HTML
+    pub fn rendered_body(&self) -> String {
+        crate::markdown::render(&self.body)
+    }
+}
+
+impl CommentNode {
+    /// Arranges `listings`, sorted oldest first, into threads
+    fn build(listings: Vec<CommentListing>) -> Vec<CommentNode> {
+        let mut children: HashMap<Option<i32>, Vec<CommentListing>> = HashMap::new();
+        for listing in listings {
+            children
+                .entry(listing.comment.parent_id)
+                .or_default()
+                .push(listing);
+        }
+        CommentNode::replies_to(&mut children, None)
+    }
+
+    fn replies_to(
+        children: &mut HashMap<Option<i32>, Vec<CommentListing>>,
+        parent_id: Option<i32>,
+    ) -> Vec<CommentNode> {
+        children
+            .remove(&parent_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|listing| {
+                let replies = CommentNode::replies_to(children, Some(listing.comment.id));
+                CommentNode { listing, replies }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::posts::Post;
+    use crate::db::models::users::User;
+    use crate::db::test_db;
+
+    #[test]
+    fn deleting_a_comment_keeps_replies_of_others() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let author = User::create(conn, test_db::unique("author")).unwrap();
+        let replier = User::create(conn, test_db::unique("replier")).unwrap();
+        let now = chrono::Utc::now().naive_utc();
+        let post = Post::create(conn, "Title", "Body", &Some(author.id), now, true, None).unwrap();
+
+        let parent = Comment::create(conn, post.id, None, author.id, "parent").unwrap();
+        let reply = Comment::create(conn, post.id, Some(&parent), replier.id, "reply").unwrap();
+
+        assert_eq!(
+            Comment::delete(conn, parent.id).unwrap(),
+            CommentDeletion::Tombstoned
+        );
+        let tombstone = Comment::find(conn, parent.id).unwrap().unwrap();
+        assert!(tombstone.is_deleted());
+        assert_eq!(tombstone.author_id, None);
+        assert_eq!(tombstone.body, "");
+        assert!(!tombstone.accepts_replies());
+        assert!(Comment::find(conn, reply.id).unwrap().is_some());
+
+        // The last reply takes the tombstone with it.
+        assert_eq!(
+            Comment::delete(conn, reply.id).unwrap(),
+            CommentDeletion::Removed {
+                outermost_id: parent.id
+            }
+        );
+        assert!(Comment::find(conn, parent.id).unwrap().is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::db::schema::{invites, users};
+use crate::tokens::{generate_token, hash_token};
+use diesel::prelude::*;
+
+/// Who may create an account, set with `SIGNUP_MODE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignupMode {
+    /// Anyone can sign up
+    #[default]
+    Open,
+    /// Signing up needs a valid invite code
+    InviteOnly,
+    /// Nobody can sign up; existing users can still sign in
+    Closed,
+}
+
+impl SignupMode {
+    /// Reads `SIGNUP_MODE` (`open`, `invite` or `closed`), defaulting to open
+    pub fn from_env() -> Self {
+        match std::env::var("SIGNUP_MODE").as_deref() {
+            Err(_) | Ok("") | Ok("open") => SignupMode::Open,
+            Ok("invite") => SignupMode::InviteOnly,
+            Ok("closed") => SignupMode::Closed,
+            Ok(other) => panic!("SIGNUP_MODE must be open, invite or closed, not {other:?}"),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self == SignupMode::Closed
+    }
+
+    pub fn requires_invite(&self) -> bool {
+        *self == SignupMode::InviteOnly
+    }
+}
+
+/// An invite code letting `max_uses` people sign up while signup is invite-only.
+/// Only the hash of the code is stored; the plain code is shown once on creation.
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = invites)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Invite {
+    pub id: i32,
+    pub code_hash: String,
+    /// User who created the invite, `None` once they deleted their account
+    pub inviter_id: Option<i32>,
+    /// Reminder of who the invite is for
+    pub note: String,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// An invite with the username of its inviter, as listed on the invites page.
+#[derive(Clone, Debug)]
+pub struct InviteListing {
+    pub invite: Invite,
+    pub inviter: Option<String>,
+}
+
+impl Invite {
+    /// Create an invite from `inviter_id`, returning it along with the plain code
+    pub fn create(
+        conn: &mut diesel::PgConnection,
+        inviter_id: i32,
+        note: &str,
+        max_uses: i32,
+        expires_at: Option<chrono::NaiveDateTime>,
+    ) -> Result<(Invite, String), diesel::result::Error> {
+        let code = generate_token();
+        let created = diesel::insert_into(invites::table)
+            .values((
+                invites::code_hash.eq(hash_token(&code)),
+                invites::inviter_id.eq(inviter_id),
+                invites::note.eq(note),
+                invites::max_uses.eq(max_uses),
+                invites::created_at.eq(chrono::Utc::now().naive_utc()),
+                invites::expires_at.eq(expires_at),
+            ))
+            .returning(Invite::as_returning())
+            .get_result(conn)?;
+        Ok((created, code))
+    }
+
+    /// Use up one signup of the invite for `code`.
+    ///
+    /// Returns `None` for unknown, expired and used up codes. The check and the update are a
+    /// single statement, so concurrent signups cannot use a code more than `max_uses` times.
+    pub fn redeem(
+        conn: &mut diesel::PgConnection,
+        code: &str,
+    ) -> Result<Option<Invite>, diesel::result::Error> {
+        diesel::update(
+            invites::table
+                .filter(invites::code_hash.eq(hash_token(code)))
+                .filter(invites::uses.lt(invites::max_uses))
+                .filter(
+                    invites::expires_at
+                        .is_null()
+                        .or(invites::expires_at.gt(chrono::Utc::now().naive_utc())),
+                ),
+        )
+        .set(invites::uses.eq(invites::uses + 1))
+        .returning(Invite::as_returning())
+        .get_result(conn)
+        .optional()
+    }
+
+    /// Invites created by `inviter_id`, or every invite when `None`, newest first
+    pub fn list(
+        conn: &mut diesel::PgConnection,
+        inviter_id: Option<i32>,
+    ) -> Result<Vec<InviteListing>, diesel::result::Error> {
+        let mut query = invites::table
+            .left_join(users::table)
+            .select((Invite::as_select(), users::username.nullable()))
+            .order(invites::created_at.desc())
+            .into_boxed();
+        if let Some(inviter_id) = inviter_id {
+            query = query.filter(invites::inviter_id.eq(inviter_id));
+        }
+        let rows: Vec<(Invite, Option<String>)> = query.load(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(|(invite, inviter)| InviteListing { invite, inviter })
+            .collect())
+    }
+
+    /// Number of invites `inviter_id` currently has, counted against their quota
+    pub fn count_for_inviter(
+        conn: &mut diesel::PgConnection,
+        inviter_id: i32,
+    ) -> Result<i64, diesel::result::Error> {
+        invites::table
+            .filter(invites::inviter_id.eq(inviter_id))
+            .count()
+            .get_result(conn)
+    }
+
+    /// Delete an invite, scoped to its inviter unless `inviter_id` is `None`
+    pub fn revoke(
+        conn: &mut diesel::PgConnection,
+        inviter_id: Option<i32>,
+        invite_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        let mut query = diesel::delete(invites::table)
+            .filter(invites::id.eq(invite_id))
+            .into_boxed();
+        if let Some(inviter_id) = inviter_id {
+            query = query.filter(invites::inviter_id.eq(inviter_id));
+        }
+        query.execute(conn)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= chrono::Utc::now().naive_utc())
+    }
+
+    pub fn remaining_uses(&self) -> i32 {
+        self.max_uses - self.uses
+    }
+}
@@ -0,0 +1,76 @@
+use crate::db::schema::login_attempts;
+use diesel::prelude::*;
+
+/// Failed login counter for one account or client address.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Clone, Debug, PartialEq)]
+#[diesel(table_name = login_attempts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
+pub struct LoginAttempt {
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: chrono::NaiveDateTime,
+    pub locked_until: Option<chrono::NaiveDateTime>,
+}
+
+impl LoginAttempt {
+    pub fn find(
+        conn: &mut diesel::PgConnection,
+        key: &str,
+    ) -> Result<Option<LoginAttempt>, diesel::result::Error> {
+        login_attempts::table
+            .find(key)
+            .select(LoginAttempt::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Atomically replace the counter of `key` with `update(previous)`
+    pub fn update_with(
+        conn: &mut diesel::PgConnection,
+        key: &str,
+        update: impl FnOnce(Option<LoginAttempt>) -> LoginAttempt,
+    ) -> Result<LoginAttempt, diesel::result::Error> {
+        conn.transaction(|conn| {
+            let previous = login_attempts::table
+                .find(key)
+                .select(LoginAttempt::as_select())
+                .for_update()
+                .first(conn)
+                .optional()?;
+            let next = update(previous);
+            diesel::insert_into(login_attempts::table)
+                .values(&next)
+                .on_conflict(login_attempts::key)
+                .do_update()
+                .set(&next)
+                .returning(LoginAttempt::as_returning())
+                .get_result(conn)
+        })
+    }
+
+    pub fn delete(
+        conn: &mut diesel::PgConnection,
+        key: &str,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(login_attempts::table.find(key)).execute(conn)
+    }
+
+    /// Delete counters that neither lock anything nor count towards a lockout anymore
+    pub fn delete_stale(
+        conn: &mut diesel::PgConnection,
+        last_failure_before: chrono::NaiveDateTime,
+    ) -> Result<usize, diesel::result::Error> {
+        let now = chrono::Utc::now().naive_utc();
+        diesel::delete(
+            login_attempts::table
+                .filter(login_attempts::last_failure_at.lt(last_failure_before))
+                .filter(
+                    login_attempts::locked_until
+                        .is_null()
+                        .or(login_attempts::locked_until.lt(now)),
+                ),
+        )
+        .execute(conn)
+    }
+}
@@ -9,5 +9,15 @@
 /// ```
 /// Then i recommend properly making your models here
 pub mod accounts;
+pub mod api_tokens;
+pub mod audit_events;
+pub mod comments;
+pub mod invites;
+pub mod login_attempts;
+pub mod personal_data;
 pub mod posts;
+pub mod reactions;
+pub mod recovery_codes;
+pub mod roles;
+pub mod tags;
 pub mod users;
@@ -0,0 +1,171 @@
+use super::accounts::Account;
+use super::api_tokens::ApiToken;
+use super::audit_events::AuditEvent;
+use super::comments::Comment;
+use super::posts::Post;
+use super::reactions::PostReaction;
+use super::recovery_codes::RecoveryCode;
+use super::roles::Role;
+use super::users::{User, VerificationToken};
+use crate::db::schema::{accounts, posts, sessions, users};
+use diesel::prelude::*;
+use serde::Serialize;
+
+/// Everything stored about a user, as handed out by the data export.
+///
+/// Secrets (password hashes, OAuth tokens, TOTP secret, token hashes, session ids) are left
+/// out: they are useless to the user and dangerous in a file lying in a downloads folder.
+#[derive(Serialize, Debug)]
+pub struct PersonalData {
+    pub exported_at: chrono::NaiveDateTime,
+    pub profile: ProfileData,
+    pub accounts: Vec<AccountData>,
+    pub posts: Vec<Post>,
+    pub comments: Vec<Comment>,
+    pub reactions: Vec<PostReaction>,
+    pub sessions: Vec<SessionData>,
+    pub api_tokens: Vec<ApiTokenData>,
+    pub audit_events: Vec<AuditEvent>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProfileData {
+    pub id: i32,
+    pub username: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub roles: Vec<String>,
+    pub two_factor_enabled: bool,
+    pub unused_recovery_codes: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccountData {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub email: Option<String>,
+    pub email_verified: Option<chrono::NaiveDateTime>,
+    pub provider: Option<String>,
+    pub provider_account_id: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl From<Account> for AccountData {
+    fn from(account: Account) -> Self {
+        AccountData {
+            type_: account.type_,
+            email: account.email,
+            email_verified: account.email_verified,
+            provider: account.provider,
+            provider_account_id: account.provider_account_id,
+            scope: account.scope,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionData {
+    pub expires: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApiTokenData {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<ApiToken> for ApiTokenData {
+    fn from(token: ApiToken) -> Self {
+        ApiTokenData {
+            name: token.name,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+            expires_at: token.expires_at,
+        }
+    }
+}
+
+impl PersonalData {
+    /// Gather the data export of `user_id`
+    pub fn collect(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<PersonalData, diesel::result::Error> {
+        let user: User = users::table
+            .find(user_id)
+            .select(User::as_select())
+            .first(conn)?;
+        let roles = Role::for_user(conn, user_id)?
+            .map(|user_roles| user_roles.roles)
+            .unwrap_or_default();
+
+        let accounts = accounts::table
+            .filter(accounts::user_id.eq(user_id))
+            .order(accounts::id)
+            .select(Account::as_select())
+            .load(conn)?;
+        let posts = posts::table
+            .filter(posts::author_id.eq(user_id))
+            .order(posts::created_at)
+            .select(Post::as_select())
+            .load(conn)?;
+        let comments = Comment::list_for_author(conn, user_id)?;
+        let reactions = PostReaction::list_for_user(conn, user_id)?;
+        let sessions = sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .order(sessions::expires)
+            .select(sessions::expires)
+            .load(conn)?;
+        let api_tokens = ApiToken::list_for_user(conn, user_id)?;
+        let audit_events = AuditEvent::list_for_user(conn, user_id)?;
+
+        Ok(PersonalData {
+            exported_at: chrono::Utc::now().naive_utc(),
+            profile: ProfileData {
+                id: user.id,
+                username: user.username,
+                created_at: user.created_at,
+                roles,
+                two_factor_enabled: user.totp_secret.is_some(),
+                unused_recovery_codes: RecoveryCode::remaining(conn, user_id)?,
+            },
+            accounts: accounts.into_iter().map(AccountData::from).collect(),
+            posts,
+            comments,
+            reactions,
+            sessions: sessions
+                .into_iter()
+                .map(|expires| SessionData { expires })
+                .collect(),
+            api_tokens: api_tokens.into_iter().map(ApiTokenData::from).collect(),
+            audit_events,
+        })
+    }
+
+    /// Delete `user_id` and everything tied to them, returning their email addresses.
+    ///
+    /// Rows referencing the user go through their foreign keys: accounts, sessions, tokens,
+    /// roles and reactions cascade, while posts, comments and audit events are kept with a
+    /// `NULL` user. Pending mailed tokens are keyed by email address instead and are revoked
+    /// here.
+    pub fn delete(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<String>, diesel::result::Error> {
+        conn.transaction(|conn| {
+            let emails: Vec<String> = accounts::table
+                .filter(accounts::user_id.eq(user_id))
+                .filter(accounts::email.is_not_null())
+                .select(accounts::email.assume_not_null())
+                .load(conn)?;
+            for email in &emails {
+                VerificationToken::revoke_for_subject(conn, email)?;
+            }
+            diesel::delete(users::table.find(user_id)).execute(conn)?;
+            Ok(emails)
+        })
+    }
+}
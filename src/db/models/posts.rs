@@ -1,7 +1,9 @@
+use super::reactions::{PostReaction, ReactionCounts};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use diesel::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Selectable, Debug, Clone, Deserialize)]
+#[derive(Queryable, Selectable, Debug, Clone, Serialize, Deserialize)]
 #[diesel(table_name = crate::db::schema::posts)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Post {
@@ -11,6 +13,18 @@ pub struct Post {
     pub published: bool,
     pub author_id: Option<i32>,
     pub created_at: chrono::NaiveDateTime,
+    /// When a scheduled draft gets published, `None` for published posts and plain drafts
+    pub publish_at: Option<chrono::NaiveDateTime>,
+    /// Hidden by a moderator; only a moderator can publish it again
+    pub hidden: bool,
+    /// Goes up on every edit of the title or body
+    pub revision: i32,
+    /// Cached rendering of the Markdown body, see [`Post::rendered_body`]
+    #[serde(skip)]
+    pub body_html: Option<String>,
+    /// Revision `body_html` was rendered from
+    #[serde(skip)]
+    pub body_html_revision: Option<i32>,
 }
 
 #[derive(Insertable, Debug, Clone, Deserialize)]
@@ -22,6 +36,91 @@ pub struct NewPost {
     pub published: Option<bool>,
     pub author_id: Option<i32>,
     pub created_at: chrono::NaiveDateTime,
+    #[serde(default)]
+    pub publish_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Edits to a post's content; `None` fields are left as they are.
+#[derive(AsChangeset, Debug, Clone, Default)]
+#[diesel(table_name = crate::db::schema::posts)]
+pub struct PostChanges {
+    pub title: Option<String>,
+    pub body: Option<String>,
+}
+
+/// Position in the feed after which the next page starts: the `created_at` and `id` of the
+/// last post shown. Handed to clients as an opaque string, see [`PostCursor::encode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostCursor {
+    pub created_at: chrono::NaiveDateTime,
+    pub id: i32,
+}
+
+impl PostCursor {
+    pub fn after(post: &Post) -> Self {
+        PostCursor {
+            created_at: post.created_at,
+            id: post.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}:{}",
+            self.created_at.and_utc().timestamp_micros(),
+            self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    /// Parses a cursor from [`PostCursor::encode`], `None` if it is malformed
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (micros, id) = raw.split_once(':')?;
+        let created_at = chrono::DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+        Some(PostCursor {
+            created_at: created_at.naive_utc(),
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// A published post matching a search, with its title and an excerpt of its body
+/// highlighted by [`crate::search::highlight`].
+#[derive(QueryableByName, Debug, Clone)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SearchHit {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    pub id: i32,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Integer>)]
+    pub author_id: Option<i32>,
+    #[diesel(sql_type = diesel::sql_types::Timestamp)]
+    pub created_at: chrono::NaiveDateTime,
+    /// Title with the matches marked, not yet escaped
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub title_headline: String,
+    /// Excerpts of the body around the matches, not yet escaped
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub body_headline: String,
+}
+
+impl SearchHit {
+    pub fn title_html(&self) -> String {
+        crate::search::highlight(&self.title_headline)
+    }
+
+    pub fn snippet_html(&self) -> String {
+        crate::search::highlight(&self.body_headline)
+    }
+}
+
+/// One page of the feed with the cursor of the next one, `None` on the last page.
+#[derive(Debug, Clone)]
+pub struct PostPage {
+    pub posts: Vec<Post>,
+    pub next: Option<PostCursor>,
+    /// Reaction counts of the posts, loaded along with them
+    pub reactions: ReactionCounts,
 }
 
 fn default_published() -> Option<bool> {
@@ -29,38 +128,102 @@ fn default_published() -> Option<bool> {
 }
 
 impl Post {
-    pub async fn get_published() -> Vec<Post> {
-        use crate::db::schema::posts::dsl::*;
-        let mut connection = crate::db::db_utils::establish_pool()
-            .get()
-            .expect("Failed to get DB connection from pool");
-        let results: Vec<Post> = posts
-            .filter(diesel::ExpressionMethods::eq(published, true))
-            .limit(5)
+    /// Page of published posts, newest first, starting after `cursor` or at the top.
+    ///
+    /// Ties on `created_at` are broken by `id` so that no post is skipped or repeated
+    /// between pages.
+    pub fn get_published(
+        conn: &mut diesel::PgConnection,
+        cursor: Option<PostCursor>,
+        limit: i64,
+    ) -> Result<PostPage, diesel::result::Error> {
+        Post::published_page(conn, None, cursor, limit)
+    }
+
+    /// Page of published posts tagged with `tag_id`, like [`Post::get_published`]
+    pub fn get_published_with_tag(
+        conn: &mut diesel::PgConnection,
+        tag_id: i32,
+        cursor: Option<PostCursor>,
+        limit: i64,
+    ) -> Result<PostPage, diesel::result::Error> {
+        Post::published_page(conn, Some(tag_id), cursor, limit)
+    }
+
+    fn published_page(
+        conn: &mut diesel::PgConnection,
+        tag_id: Option<i32>,
+        cursor: Option<PostCursor>,
+        limit: i64,
+    ) -> Result<PostPage, diesel::result::Error> {
+        use crate::db::schema::{post_tags, posts};
+        let mut query = posts::table
+            .filter(posts::published.eq(true))
+            .order((posts::created_at.desc(), posts::id.desc()))
+            // One extra row tells whether there is a next page
+            .limit(limit + 1)
             .select(Post::as_select())
-            .load(&mut connection)
-            .expect("Error loading posts");
-        println!("Displaying {} posts", results.len());
-        results
+            .into_boxed();
+        if let Some(cursor) = cursor {
+            query = query.filter(
+                posts::created_at.lt(cursor.created_at).or(posts::created_at
+                    .eq(cursor.created_at)
+                    .and(posts::id.lt(cursor.id))),
+            );
+        }
+        if let Some(tag_id) = tag_id {
+            query = query.filter(
+                posts::id.eq_any(
+                    post_tags::table
+                        .filter(post_tags::tag_id.eq(tag_id))
+                        .select(post_tags::post_id),
+                ),
+            );
+        }
+        let mut posts: Vec<Post> = query.load(conn)?;
+        let next = if posts.len() as i64 > limit {
+            posts.truncate(limit as usize);
+            posts.last().map(PostCursor::after)
+        } else {
+            None
+        };
+        let post_ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+        let reactions = PostReaction::counts_for_posts(conn, &post_ids)?;
+        Ok(PostPage {
+            posts,
+            next,
+            reactions,
+        })
     }
 
+    /// Create a post, published right away or kept as a draft, possibly scheduled for
+    /// `publish_at`
     pub fn create(
         conn: &mut diesel::PgConnection,
         title: &str,
         body: &str,
         author_id: &Option<i32>,
         created_at_value: chrono::NaiveDateTime,
+        published: bool,
+        publish_at: Option<chrono::NaiveDateTime>,
     ) -> Option<Post> {
+        use crate::db::schema::posts;
         let new_post: NewPost = NewPost::new(
             title.to_string(),
             body.to_string(),
-            Some(true),
+            Some(published),
             *author_id,
             created_at_value,
+            publish_at.filter(|_| !published),
         );
         println!("Creating post: {new_post:?}");
-        let result = diesel::insert_into(crate::db::schema::posts::table)
-            .values(&new_post)
+        // A new post is at revision 1
+        let result = diesel::insert_into(posts::table)
+            .values((
+                &new_post,
+                posts::body_html.eq(crate::markdown::render(body)),
+                posts::body_html_revision.eq(1),
+            ))
             .returning(Post::as_returning())
             .get_result(conn);
         match result {
@@ -74,6 +237,242 @@ impl Post {
             }
         }
     }
+
+    /// Most recent published posts, newest first
+    pub fn recent_published(
+        conn: &mut diesel::PgConnection,
+        limit: i64,
+    ) -> Result<Vec<Post>, diesel::result::Error> {
+        use crate::db::schema::posts;
+        posts::table
+            .filter(posts::published.eq(true))
+            .order((posts::created_at.desc(), posts::id.desc()))
+            .limit(limit)
+            .select(Post::as_select())
+            .load(conn)
+    }
+
+    pub fn find(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+    ) -> Result<Option<Post>, diesel::result::Error> {
+        use crate::db::schema::posts;
+        posts::table
+            .find(post_id)
+            .select(Post::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Published posts matching `tsquery`, built by [`crate::search::to_tsquery`], best
+    /// matches first
+    pub fn search(
+        conn: &mut diesel::PgConnection,
+        tsquery: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SearchHit>, diesel::result::Error> {
+        use diesel::sql_types::{BigInt, Text};
+        let headline_options = |extra: &str| {
+            format!(
+                "StartSel={}, StopSel={}, {extra}",
+                crate::search::MATCH_START,
+                crate::search::MATCH_END
+            )
+        };
+        diesel::sql_query(
+            "SELECT posts.id, posts.author_id, posts.created_at, \
+                 ts_headline('english', posts.title, query, $2) AS title_headline, \
+                 ts_headline('english', posts.body, query, $3) AS body_headline \
+             FROM posts, to_tsquery('english', $1) AS query \
+             WHERE posts.published AND posts.search_vector @@ query \
+             ORDER BY ts_rank(posts.search_vector, query) DESC, posts.created_at DESC, posts.id DESC \
+             LIMIT $4 OFFSET $5",
+        )
+        .bind::<Text, _>(tsquery)
+        .bind::<Text, _>(headline_options("HighlightAll=true"))
+        .bind::<Text, _>(headline_options(
+            "MaxFragments=2, MaxWords=30, MinWords=10, FragmentDelimiter=\" … \"",
+        ))
+        .bind::<BigInt, _>(limit)
+        .bind::<BigInt, _>(offset)
+        .load(conn)
+    }
+
+    /// Whether `user_id` wrote this post
+    pub fn is_authored_by(&self, user_id: i32) -> bool {
+        self.author_id == Some(user_id)
+    }
+
+    pub fn update(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+        changes: &PostChanges,
+    ) -> Result<Post, diesel::result::Error> {
+        use crate::db::schema::posts;
+        let mut post = diesel::update(posts::table.find(post_id))
+            .set((changes, posts::revision.eq(posts::revision + 1)))
+            .returning(Post::as_returning())
+            .get_result(conn)?;
+        Post::store_rendered_bodies(conn, std::slice::from_mut(&mut post))?;
+        Ok(post)
+    }
+
+    /// The body rendered to sanitized HTML, from the cache when it matches the current
+    /// revision. Posts whose cache is stale are rendered here without storing the result,
+    /// so that reads never write; the cache is filled when a post is saved or published.
+    pub fn rendered_body(&self) -> String {
+        match &self.body_html {
+            Some(body_html) if self.body_html_revision == Some(self.revision) => body_html.clone(),
+            _ => crate::markdown::render(&self.body),
+        }
+    }
+
+    /// Renders the bodies whose cache is missing or stale and stores them.
+    ///
+    /// Each rendering is only stored if the post is still at the revision it was rendered
+    /// from, so a concurrent edit is never cached with an outdated body. Only called when
+    /// posts are saved or published, never on reads.
+    fn store_rendered_bodies(
+        conn: &mut diesel::PgConnection,
+        posts: &mut [Post],
+    ) -> Result<(), diesel::result::Error> {
+        use crate::db::schema::posts;
+        for post in posts.iter_mut().filter(|post| {
+            post.body_html.is_none() || post.body_html_revision != Some(post.revision)
+        }) {
+            let body_html = crate::markdown::render(&post.body);
+            diesel::update(
+                posts::table
+                    .find(post.id)
+                    .filter(posts::revision.eq(post.revision)),
+            )
+            .set((
+                posts::body_html.eq(&body_html),
+                posts::body_html_revision.eq(post.revision),
+            ))
+            .execute(conn)?;
+            post.body_html = Some(body_html);
+            post.body_html_revision = Some(post.revision);
+        }
+        Ok(())
+    }
+
+    pub fn delete(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::db::schema::posts;
+        diesel::delete(posts::table.find(post_id)).execute(conn)
+    }
+
+    /// Unpublished posts of `author_id`: scheduled ones first by due time, then drafts and
+    /// hidden posts, newest first
+    pub fn drafts_for_author(
+        conn: &mut diesel::PgConnection,
+        author_id: i32,
+    ) -> Result<Vec<Post>, diesel::result::Error> {
+        use crate::db::schema::posts;
+        posts::table
+            .filter(posts::author_id.eq(author_id))
+            .filter(posts::published.eq(false))
+            .order((
+                posts::publish_at.asc().nulls_last(),
+                posts::created_at.desc(),
+                posts::id.desc(),
+            ))
+            .select(Post::as_select())
+            .load(conn)
+    }
+
+    /// Publish a post now, also clearing its schedule and moderator hide.
+    ///
+    /// `created_at` is moved to now so that the post shows up at the top of the feed.
+    pub fn publish(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::db::schema::posts;
+        let mut published: Vec<Post> = diesel::update(posts::table.find(post_id))
+            .set((
+                posts::published.eq(true),
+                posts::hidden.eq(false),
+                posts::publish_at.eq(None::<chrono::NaiveDateTime>),
+                posts::created_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .returning(Post::as_returning())
+            .get_results(conn)?;
+        Post::store_rendered_bodies(conn, &mut published)?;
+        Ok(published.len())
+    }
+
+    /// Turn a post back into a draft, cancelling any schedule
+    pub fn unpublish(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::db::schema::posts;
+        diesel::update(posts::table.find(post_id))
+            .set((
+                posts::published.eq(false),
+                posts::publish_at.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .execute(conn)
+    }
+
+    /// Turn a post into a draft published automatically at `publish_at`
+    pub fn schedule(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+        publish_at: chrono::NaiveDateTime,
+    ) -> Result<Post, diesel::result::Error> {
+        use crate::db::schema::posts;
+        diesel::update(posts::table.find(post_id))
+            .set((posts::published.eq(false), posts::publish_at.eq(publish_at)))
+            .returning(Post::as_returning())
+            .get_result(conn)
+    }
+
+    /// Unpublish a post for moderation; its author cannot publish it again
+    pub fn hide(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::db::schema::posts;
+        diesel::update(posts::table.find(post_id))
+            .set((
+                posts::published.eq(false),
+                posts::hidden.eq(true),
+                posts::publish_at.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .execute(conn)
+    }
+
+    /// Publish every scheduled draft that is due, dated at its scheduled time.
+    /// Returns how many posts were published.
+    pub fn publish_due(conn: &mut diesel::PgConnection) -> Result<usize, diesel::result::Error> {
+        use crate::db::schema::posts;
+        let mut published: Vec<Post> = diesel::update(
+            posts::table
+                .filter(posts::published.eq(false))
+                .filter(posts::hidden.eq(false))
+                .filter(posts::publish_at.le(chrono::Utc::now().naive_utc())),
+        )
+        .set((
+            posts::published.eq(true),
+            posts::created_at.eq(posts::publish_at.assume_not_null()),
+            posts::publish_at.eq(None::<chrono::NaiveDateTime>),
+        ))
+        .returning(Post::as_returning())
+        .get_results(conn)?;
+        Post::store_rendered_bodies(conn, &mut published)?;
+        Ok(published.len())
+    }
+
+    /// Whether this is a draft waiting for its scheduled time
+    pub fn is_scheduled(&self) -> bool {
+        !self.published && self.publish_at.is_some()
+    }
 }
 
 impl NewPost {
@@ -83,6 +482,7 @@ impl NewPost {
         published: Option<bool>,
         author_id: Option<i32>,
         created_at_value: chrono::NaiveDateTime,
+        publish_at: Option<chrono::NaiveDateTime>,
     ) -> Self {
         NewPost {
             title,
@@ -90,6 +490,74 @@ impl NewPost {
             published,
             author_id,
             created_at: created_at_value,
+            publish_at,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::schema::posts;
+    use crate::db::test_db;
+
+    fn stored_body_html(conn: &mut diesel::PgConnection, post_id: i32) -> Option<String> {
+        posts::table
+            .find(post_id)
+            .select(posts::body_html)
+            .first(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn bodies_are_rendered_when_saved() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let now = chrono::Utc::now().naive_utc();
+
+        let post = Post::create(conn, "Title", "*first*", &None, now, true, None).unwrap();
+        assert_eq!(post.body_html_revision, Some(post.revision));
+        assert_eq!(
+            stored_body_html(conn, post.id).as_deref(),
+            Some("<p><em>first</em></p>\n")
+        );
+
+        let changes = PostChanges {
+            body: Some("**second**".to_string()),
+            ..Default::default()
+        };
+        let post = Post::update(conn, post.id, &changes).unwrap();
+        assert_eq!(post.body_html_revision, Some(post.revision));
+        assert_eq!(
+            stored_body_html(conn, post.id).as_deref(),
+            Some("<p><strong>second</strong></p>\n")
+        );
+    }
+
+    #[test]
+    fn reading_the_feed_does_not_write() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let now = chrono::Utc::now().naive_utc();
+        let post = Post::create(conn, "Title", "*body*", &None, now, true, None).unwrap();
+        diesel::update(posts::table.find(post.id))
+            .set(posts::body_html.eq(None::<String>))
+            .execute(conn)
+            .unwrap();
+
+        let page = Post::published_page(conn, None, None, 100).unwrap();
+        let shown = page.posts.iter().find(|shown| shown.id == post.id).unwrap();
+        assert_eq!(shown.rendered_body(), "<p><em>body</em></p>\n");
+        assert_eq!(stored_body_html(conn, post.id), None);
+
+        Post::publish(conn, post.id).unwrap();
+        assert_eq!(
+            stored_body_html(conn, post.id).as_deref(),
+            Some("<p><em>body</em></p>\n")
+        );
+    }
+}
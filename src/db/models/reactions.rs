@@ -0,0 +1,161 @@
+use crate::db::schema::post_reactions;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// A user's reaction to a post. Each user can give each kind of reaction once per post.
+#[derive(Queryable, Selectable, Clone, Debug, Serialize)]
+#[diesel(table_name = post_reactions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PostReaction {
+    pub post_id: i32,
+    pub user_id: i32,
+    /// One of the kinds in [`PostReaction::KINDS`]
+    pub reaction: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Number of reactions of each kind, per post
+pub type ReactionCounts = HashMap<i32, HashMap<String, i64>>;
+
+/// One kind of reaction on a post, as shown under it.
+#[derive(Clone, Debug)]
+pub struct ReactionSummary {
+    pub kind: &'static str,
+    pub emoji: &'static str,
+    pub count: i64,
+    /// Whether the viewer gave this reaction
+    pub reacted: bool,
+}
+
+/// The reactions of a post seen by one user, in the order of [`PostReaction::KINDS`].
+#[derive(Clone, Debug)]
+pub struct Reactions {
+    pub post_id: i32,
+    pub summaries: Vec<ReactionSummary>,
+}
+
+impl PostReaction {
+    /// Every kind of reaction with its emoji
+    pub const KINDS: [(&'static str, &'static str); 4] = [
+        ("like", "👍"),
+        ("love", "❤️"),
+        ("laugh", "😂"),
+        ("celebrate", "🎉"),
+    ];
+
+    pub fn is_kind(reaction: &str) -> bool {
+        PostReaction::KINDS
+            .iter()
+            .any(|(kind, _)| *kind == reaction)
+    }
+
+    /// Give `reaction` to `post_id` as `user_id`, or take it back if it was given.
+    ///
+    /// Returns whether the user now has the reaction.
+    pub fn toggle(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+        user_id: i32,
+        reaction: &str,
+    ) -> Result<bool, diesel::result::Error> {
+        let removed = diesel::delete(
+            post_reactions::table
+                .filter(post_reactions::post_id.eq(post_id))
+                .filter(post_reactions::user_id.eq(user_id))
+                .filter(post_reactions::reaction.eq(reaction)),
+        )
+        .execute(conn)?;
+        if removed > 0 {
+            return Ok(false);
+        }
+        diesel::insert_into(post_reactions::table)
+            .values((
+                post_reactions::post_id.eq(post_id),
+                post_reactions::user_id.eq(user_id),
+                post_reactions::reaction.eq(reaction),
+                post_reactions::created_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(true)
+    }
+
+    /// Reaction counts of each of `post_ids`, in a single query
+    pub fn counts_for_posts(
+        conn: &mut diesel::PgConnection,
+        post_ids: &[i32],
+    ) -> Result<ReactionCounts, diesel::result::Error> {
+        let rows: Vec<(i32, String, i64)> = post_reactions::table
+            .filter(post_reactions::post_id.eq_any(post_ids))
+            .group_by((post_reactions::post_id, post_reactions::reaction))
+            .select((
+                post_reactions::post_id,
+                post_reactions::reaction,
+                diesel::dsl::count_star(),
+            ))
+            .load(conn)?;
+        let mut counts = ReactionCounts::new();
+        for (post_id, reaction, count) in rows {
+            counts.entry(post_id).or_default().insert(reaction, count);
+        }
+        Ok(counts)
+    }
+
+    /// The `(post_id, reaction)` pairs `user_id` gave among `post_ids`
+    pub fn given_by(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        post_ids: &[i32],
+    ) -> Result<HashSet<(i32, String)>, diesel::result::Error> {
+        let rows: Vec<(i32, String)> = post_reactions::table
+            .filter(post_reactions::user_id.eq(user_id))
+            .filter(post_reactions::post_id.eq_any(post_ids))
+            .select((post_reactions::post_id, post_reactions::reaction))
+            .load(conn)?;
+        Ok(rows.into_iter().collect())
+    }
+
+    /// Reactions of `user_id`, oldest first, for the personal data export
+    pub fn list_for_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<PostReaction>, diesel::result::Error> {
+        post_reactions::table
+            .filter(post_reactions::user_id.eq(user_id))
+            .order(post_reactions::created_at)
+            .select(PostReaction::as_select())
+            .load(conn)
+    }
+}
+
+impl Reactions {
+    /// Reactions of `post_id` from counts and the viewer's reactions loaded beforehand
+    pub fn new(post_id: i32, counts: &ReactionCounts, given: &HashSet<(i32, String)>) -> Self {
+        let counts = counts.get(&post_id);
+        let summaries = PostReaction::KINDS
+            .iter()
+            .map(|(kind, emoji)| ReactionSummary {
+                kind,
+                emoji,
+                count: counts
+                    .and_then(|counts| counts.get(*kind))
+                    .copied()
+                    .unwrap_or_default(),
+                reacted: given.contains(&(post_id, kind.to_string())),
+            })
+            .collect();
+        Reactions { post_id, summaries }
+    }
+
+    /// Reactions of a single post seen by `user_id`
+    pub fn load(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+        user_id: i32,
+    ) -> Result<Self, diesel::result::Error> {
+        let counts = PostReaction::counts_for_posts(conn, &[post_id])?;
+        let given = PostReaction::given_by(conn, user_id, &[post_id])?;
+        Ok(Reactions::new(post_id, &counts, &given))
+    }
+}
@@ -0,0 +1,82 @@
+use crate::db::schema::recovery_codes;
+use crate::tokens::hash_token;
+use crate::two_factor::normalize_recovery_code;
+use diesel::prelude::*;
+
+/// One-time code that can stand in for a TOTP code, e.g. when a phone is lost.
+/// Only the hash of the normalized code is stored.
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = recovery_codes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub user_id: i32,
+    pub code_hash: String,
+    pub used_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl RecoveryCode {
+    /// Replace every recovery code of `user_id` with `codes`
+    pub fn replace_for_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        codes: &[String],
+    ) -> Result<usize, diesel::result::Error> {
+        let now = chrono::Utc::now().naive_utc();
+        let rows: Vec<_> = codes
+            .iter()
+            .map(|code| {
+                (
+                    recovery_codes::user_id.eq(user_id),
+                    recovery_codes::code_hash.eq(hash_token(&normalize_recovery_code(code))),
+                    recovery_codes::created_at.eq(now),
+                )
+            })
+            .collect();
+
+        conn.transaction(|conn| {
+            RecoveryCode::delete_for_user(conn, user_id)?;
+            diesel::insert_into(recovery_codes::table)
+                .values(&rows)
+                .execute(conn)
+        })
+    }
+
+    /// Mark a matching unused code as used, returning whether one was found
+    pub fn consume(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        code: &str,
+    ) -> Result<bool, diesel::result::Error> {
+        let updated = diesel::update(
+            recovery_codes::table
+                .filter(recovery_codes::user_id.eq(user_id))
+                .filter(recovery_codes::code_hash.eq(hash_token(&normalize_recovery_code(code))))
+                .filter(recovery_codes::used_at.is_null()),
+        )
+        .set(recovery_codes::used_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+        Ok(updated > 0)
+    }
+
+    /// Number of codes of `user_id` that have not been used yet
+    pub fn remaining(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<i64, diesel::result::Error> {
+        recovery_codes::table
+            .filter(recovery_codes::user_id.eq(user_id))
+            .filter(recovery_codes::used_at.is_null())
+            .count()
+            .get_result(conn)
+    }
+
+    pub fn delete_for_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+            .execute(conn)
+    }
+}
@@ -0,0 +1,181 @@
+use crate::db::schema::{permissions, role_permissions, roles, user_roles, users};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A named group of permissions that can be assigned to users.
+#[derive(Queryable, Selectable, Clone, Debug, Serialize, Deserialize)]
+#[diesel(table_name = roles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Role {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+}
+
+/// A permission checked by route guards and handlers, see `AuthzBackend for Backend`.
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[diesel(table_name = permissions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Permission {
+    pub name: String,
+}
+
+impl Permission {
+    /// Access to the admin pages, including user and role management
+    pub const ADMIN: &'static str = "admin.access";
+    /// Moderation of content written by other users
+    pub const MODERATE: &'static str = "posts.moderate";
+
+    /// Every permission granted to `user_id` through their roles
+    pub fn for_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<Permission>, diesel::result::Error> {
+        user_roles::table
+            .inner_join(
+                role_permissions::table.on(role_permissions::role_id.eq(user_roles::role_id)),
+            )
+            .inner_join(permissions::table.on(permissions::id.eq(role_permissions::permission_id)))
+            .filter(user_roles::user_id.eq(user_id))
+            .select(Permission::as_select())
+            .distinct()
+            .load(conn)
+    }
+}
+
+impl From<&str> for Permission {
+    fn from(name: &str) -> Self {
+        Permission {
+            name: name.to_string(),
+        }
+    }
+}
+
+/// A user together with the names of their roles, for the admin pages.
+#[derive(Clone, Debug)]
+pub struct UserRoles {
+    pub user_id: i32,
+    pub username: String,
+    pub roles: Vec<String>,
+}
+
+impl UserRoles {
+    pub fn has_role(&self, name: &str) -> bool {
+        self.roles.iter().any(|role| role == name)
+    }
+}
+
+impl Role {
+    pub const ADMIN: &'static str = "admin";
+    pub const MODERATOR: &'static str = "moderator";
+
+    pub fn all(conn: &mut diesel::PgConnection) -> Result<Vec<Role>, diesel::result::Error> {
+        roles::table
+            .order(roles::id)
+            .select(Role::as_select())
+            .load(conn)
+    }
+
+    pub fn find_by_name(
+        conn: &mut diesel::PgConnection,
+        name: &str,
+    ) -> Result<Option<Role>, diesel::result::Error> {
+        roles::table
+            .filter(roles::name.eq(name))
+            .select(Role::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Assign `role_id` to `user_id`, doing nothing if it is already assigned
+    pub fn assign(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        role_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::insert_into(user_roles::table)
+            .values((
+                user_roles::user_id.eq(user_id),
+                user_roles::role_id.eq(role_id),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+    }
+
+    pub fn revoke(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        role_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(
+            user_roles::table
+                .filter(user_roles::user_id.eq(user_id))
+                .filter(user_roles::role_id.eq(role_id)),
+        )
+        .execute(conn)
+    }
+
+    /// Number of users holding the role called `name`
+    pub fn count_users(
+        conn: &mut diesel::PgConnection,
+        name: &str,
+    ) -> Result<i64, diesel::result::Error> {
+        user_roles::table
+            .inner_join(roles::table)
+            .filter(roles::name.eq(name))
+            .count()
+            .get_result(conn)
+    }
+
+    /// Every user with their role names, ordered by username
+    pub fn list_users(
+        conn: &mut diesel::PgConnection,
+    ) -> Result<Vec<UserRoles>, diesel::result::Error> {
+        let rows: Vec<(i32, String, Option<String>)> = users::table
+            .left_join(user_roles::table.inner_join(roles::table))
+            .order((users::username, users::id, roles::id.nullable()))
+            .select((users::id, users::username, roles::name.nullable()))
+            .load(conn)?;
+
+        let mut result: Vec<UserRoles> = Vec::new();
+        for (user_id, username, role) in rows {
+            if result.last().is_none_or(|last| last.user_id != user_id) {
+                result.push(UserRoles {
+                    user_id,
+                    username,
+                    roles: Vec::new(),
+                });
+            }
+            if let (Some(role), Some(last)) = (role, result.last_mut()) {
+                last.roles.push(role);
+            }
+        }
+        Ok(result)
+    }
+
+    /// A single user with their role names
+    pub fn for_user(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<Option<UserRoles>, diesel::result::Error> {
+        let Some(username) = users::table
+            .find(user_id)
+            .select(users::username)
+            .first::<String>(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let roles = user_roles::table
+            .inner_join(roles::table)
+            .filter(user_roles::user_id.eq(user_id))
+            .order(roles::id)
+            .select(roles::name)
+            .load(conn)?;
+        Ok(Some(UserRoles {
+            user_id,
+            username,
+            roles,
+        }))
+    }
+}
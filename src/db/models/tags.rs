@@ -0,0 +1,126 @@
+use crate::db::schema::{post_tags, posts, tags};
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+/// A tag, named in the form given by [`crate::validation::normalize_tag`].
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+}
+
+/// A tag with the number of published posts carrying it, as shown in the tag cloud.
+#[derive(Clone, Debug)]
+pub struct TagCount {
+    pub name: String,
+    pub posts: i64,
+}
+
+impl Tag {
+    pub fn find_by_name(
+        conn: &mut diesel::PgConnection,
+        name: &str,
+    ) -> Result<Option<Tag>, diesel::result::Error> {
+        tags::table
+            .filter(tags::name.eq(name))
+            .select(Tag::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Replace the tags of `post_id` with `names`, which must already be normalized.
+    ///
+    /// Tags are created on first use; tags no post uses any more are kept.
+    pub fn set_for_post(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+        names: &[String],
+    ) -> Result<(), diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::insert_into(tags::table)
+                .values(
+                    names
+                        .iter()
+                        .map(|name| tags::name.eq(name))
+                        .collect::<Vec<_>>(),
+                )
+                .on_conflict(tags::name)
+                .do_nothing()
+                .execute(conn)?;
+            let tag_ids: Vec<i32> = tags::table
+                .filter(tags::name.eq_any(names))
+                .select(tags::id)
+                .load(conn)?;
+
+            diesel::delete(
+                post_tags::table
+                    .filter(post_tags::post_id.eq(post_id))
+                    .filter(post_tags::tag_id.ne_all(&tag_ids)),
+            )
+            .execute(conn)?;
+            diesel::insert_into(post_tags::table)
+                .values(
+                    tag_ids
+                        .iter()
+                        .map(|tag_id| {
+                            (post_tags::post_id.eq(post_id), post_tags::tag_id.eq(tag_id))
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Tag names of each of `post_ids`, sorted by name, in a single query
+    pub fn names_for_posts(
+        conn: &mut diesel::PgConnection,
+        post_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<String>>, diesel::result::Error> {
+        let rows: Vec<(i32, String)> = post_tags::table
+            .inner_join(tags::table)
+            .filter(post_tags::post_id.eq_any(post_ids))
+            .order(tags::name.asc())
+            .select((post_tags::post_id, tags::name))
+            .load(conn)?;
+        let mut names: HashMap<i32, Vec<String>> = HashMap::new();
+        for (post_id, name) in rows {
+            names.entry(post_id).or_default().push(name);
+        }
+        Ok(names)
+    }
+
+    /// Tag names of one post, sorted by name
+    pub fn names_for_post(
+        conn: &mut diesel::PgConnection,
+        post_id: i32,
+    ) -> Result<Vec<String>, diesel::result::Error> {
+        Ok(Tag::names_for_posts(conn, &[post_id])?
+            .remove(&post_id)
+            .unwrap_or_default())
+    }
+
+    /// The `limit` tags on the most published posts, sorted by name
+    pub fn cloud(
+        conn: &mut diesel::PgConnection,
+        limit: i64,
+    ) -> Result<Vec<TagCount>, diesel::result::Error> {
+        let rows: Vec<(String, i64)> = tags::table
+            .inner_join(post_tags::table.inner_join(posts::table))
+            .filter(posts::published.eq(true))
+            .group_by(tags::name)
+            .select((tags::name, diesel::dsl::count_star()))
+            .order((diesel::dsl::count_star().desc(), tags::name.asc()))
+            .limit(limit)
+            .load(conn)?;
+        let mut cloud: Vec<TagCount> = rows
+            .into_iter()
+            .map(|(name, posts)| TagCount { name, posts })
+            .collect();
+        cloud.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(cloud)
+    }
+}
@@ -1,10 +1,16 @@
-use super::accounts::Account;
+use super::accounts::{Account, OAuthTokens};
+use super::invites::SignupMode;
+use super::roles::Permission;
+use crate::db::db_utils::{lower, new_security_stamp};
 use crate::db::schema::{sessions, users as users_table, verification_tokens};
+use crate::oauth::OAuthIdentity;
+use crate::validation::{USERNAME_MAX_LEN, normalize_email, sanitize_username};
 use async_trait::async_trait;
-use axum_login::{AuthUser, AuthnBackend, UserId};
+use axum_login::{AuthUser, AuthnBackend, AuthzBackend, UserId};
 use diesel::prelude::*;
 use password_auth::verify_password;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use tokio::task;
 
 /// Core user entity containing basic user information.
@@ -16,6 +22,10 @@ pub struct User {
     pub id: i32,
     pub username: String,
     pub created_at: chrono::NaiveDateTime,
+    /// TOTP secret, `Some` when two-factor authentication is enabled
+    pub totp_secret: Option<String>,
+    /// Rotated whenever credentials change so that existing sessions stop validating
+    pub security_stamp: String,
 }
 
 impl std::fmt::Debug for User {
@@ -28,14 +38,20 @@ impl std::fmt::Debug for User {
     }
 }
 
-#[derive(Queryable, Selectable, Clone, Serialize, Deserialize, Debug)]
+/// A persisted `tower_sessions` record, see [`crate::db::session_store::PgSessionStore`].
+#[derive(Queryable, Selectable, Insertable, Clone, Serialize, Deserialize, Debug)]
 #[diesel(table_name = sessions)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Session {
     pub id: String,
-    pub session_token: String,
-    pub user_id: i32,
+    /// Logged-in user, `None` for anonymous sessions
+    pub user_id: Option<i32>,
     pub expires: chrono::NaiveDateTime,
+    pub data: serde_json::Value,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_active_at: chrono::NaiveDateTime,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
 }
 
 #[derive(Queryable, Selectable, Clone, Serialize, Deserialize, Debug)]
@@ -47,7 +63,113 @@ pub struct VerificationToken {
     pub expires: chrono::NaiveDateTime,
 }
 
+impl VerificationToken {
+    /// Purpose prefix of the tokens mailed after signup to confirm an email address
+    pub const VERIFY_EMAIL: &'static str = "verify-email";
+    /// Purpose prefix of the tokens mailed from the "forgot password" form
+    pub const RESET_PASSWORD: &'static str = "reset-password";
+    /// Purpose prefix of the passwordless sign-in links mailed from the login form
+    pub const MAGIC_LINK: &'static str = "magic-link";
+
+    /// Revoke every pending token of `subject`, whatever its purpose
+    pub fn revoke_for_subject(
+        conn: &mut diesel::PgConnection,
+        subject: &str,
+    ) -> Result<usize, diesel::result::Error> {
+        let identifiers: Vec<String> = [Self::VERIFY_EMAIL, Self::RESET_PASSWORD, Self::MAGIC_LINK]
+            .iter()
+            .map(|purpose| format!("{purpose}:{subject}"))
+            .collect();
+        diesel::delete(
+            verification_tokens::table.filter(verification_tokens::identifier.eq_any(identifiers)),
+        )
+        .execute(conn)
+    }
+
+    /// Issue a new single-use token for `purpose` and `subject` (usually an email address).
+    ///
+    /// Any previous token for the same identifier is revoked. Only the hash of the token is
+    /// stored; the returned plain token is what gets sent to the user.
+    pub fn issue(
+        conn: &mut diesel::PgConnection,
+        purpose: &str,
+        subject: &str,
+        ttl: chrono::Duration,
+    ) -> Result<String, diesel::result::Error> {
+        let identifier = format!("{purpose}:{subject}");
+        let token = crate::tokens::generate_token();
+
+        conn.transaction(|conn| {
+            diesel::delete(
+                verification_tokens::table.filter(verification_tokens::identifier.eq(&identifier)),
+            )
+            .execute(conn)?;
+
+            diesel::insert_into(verification_tokens::table)
+                .values((
+                    verification_tokens::identifier.eq(&identifier),
+                    verification_tokens::token.eq(crate::tokens::hash_token(&token)),
+                    verification_tokens::expires.eq(chrono::Utc::now().naive_utc() + ttl),
+                ))
+                .execute(conn)
+        })?;
+
+        Ok(token)
+    }
+
+    /// Look up a token issued for `purpose` without consuming it, returning its subject when
+    /// it is still valid.
+    pub fn peek(
+        conn: &mut diesel::PgConnection,
+        purpose: &str,
+        token: &str,
+    ) -> Result<Option<String>, diesel::result::Error> {
+        let found = verification_tokens::table
+            .filter(verification_tokens::token.eq(crate::tokens::hash_token(token)))
+            .filter(verification_tokens::identifier.like(format!("{purpose}:%")))
+            .filter(verification_tokens::expires.gt(chrono::Utc::now().naive_utc()))
+            .select(VerificationToken::as_select())
+            .first(conn)
+            .optional()?;
+
+        Ok(found.and_then(|t| {
+            t.identifier
+                .strip_prefix(&format!("{purpose}:"))
+                .map(str::to_string)
+        }))
+    }
+
+    /// Consume a token issued for `purpose`, returning its subject when it is still valid.
+    ///
+    /// The row is deleted whether or not it has expired, so a token can never be used twice.
+    pub fn consume(
+        conn: &mut diesel::PgConnection,
+        purpose: &str,
+        token: &str,
+    ) -> Result<Option<String>, diesel::result::Error> {
+        let consumed = diesel::delete(
+            verification_tokens::table
+                .filter(verification_tokens::token.eq(crate::tokens::hash_token(token)))
+                .filter(verification_tokens::identifier.like(format!("{purpose}:%"))),
+        )
+        .returning(VerificationToken::as_returning())
+        .get_result(conn)
+        .optional()?;
+
+        Ok(consumed
+            .filter(|t| t.expires > chrono::Utc::now().naive_utc())
+            .and_then(|t| {
+                t.identifier
+                    .strip_prefix(&format!("{purpose}:"))
+                    .map(str::to_string)
+            }))
+    }
+}
+
 impl User {
+    /// Name of the unique index on lowercased usernames
+    pub const USERNAME_UNIQUE_INDEX: &'static str = "users_username_lower_idx";
+
     /// Create a new user with the given username
     pub fn create(
         conn: &mut diesel::PgConnection,
@@ -65,6 +187,100 @@ impl User {
             .returning(User::as_returning())
             .get_result(conn)
     }
+
+    /// Whether `username` is in use, ignoring case
+    pub fn username_taken(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+    ) -> Result<bool, diesel::result::Error> {
+        diesel::select(diesel::dsl::exists(
+            users_table::table.filter(lower(users_table::username).eq(username.to_lowercase())),
+        ))
+        .get_result(conn)
+    }
+
+    /// A free, valid username derived from `suggestion`, adding a numeric suffix if needed
+    pub fn available_username(
+        conn: &mut diesel::PgConnection,
+        suggestion: &str,
+    ) -> Result<String, diesel::result::Error> {
+        let base = sanitize_username(suggestion);
+        if !Self::username_taken(conn, &base)? {
+            return Ok(base);
+        }
+        for suffix in 2.. {
+            let suffix = format!("-{suffix}");
+            let mut candidate: String =
+                base.chars().take(USERNAME_MAX_LEN - suffix.len()).collect();
+            candidate.push_str(&suffix);
+            if !Self::username_taken(conn, &candidate)? {
+                return Ok(candidate);
+            }
+        }
+        unreachable!("ran out of username suffixes")
+    }
+
+    /// Enable two-factor authentication with a confirmed TOTP secret
+    pub fn enable_totp(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        secret: &str,
+        confirmed_step: i64,
+    ) -> Result<User, diesel::result::Error> {
+        diesel::update(users_table::table.find(user_id))
+            .set((
+                users_table::totp_secret.eq(secret),
+                users_table::totp_last_step.eq(confirmed_step),
+            ))
+            .returning(User::as_returning())
+            .get_result(conn)
+    }
+
+    /// Disable two-factor authentication
+    pub fn disable_totp(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<User, diesel::result::Error> {
+        diesel::update(users_table::table.find(user_id))
+            .set((
+                users_table::totp_secret.eq(None::<String>),
+                users_table::totp_last_step.eq(None::<i64>),
+            ))
+            .returning(User::as_returning())
+            .get_result(conn)
+    }
+
+    /// Record that the TOTP code of `step` has been used.
+    ///
+    /// Returns `false` when this step, or a later one, was already used, so that a code
+    /// cannot be replayed while it is still valid.
+    pub fn use_totp_step(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+        step: i64,
+    ) -> Result<bool, diesel::result::Error> {
+        let updated = diesel::update(
+            users_table::table.find(user_id).filter(
+                users_table::totp_last_step
+                    .is_null()
+                    .or(users_table::totp_last_step.lt(step)),
+            ),
+        )
+        .set(users_table::totp_last_step.eq(step))
+        .execute(conn)?;
+        Ok(updated == 1)
+    }
+
+    /// Replace the security stamp, invalidating every session of this user
+    pub fn rotate_security_stamp(
+        conn: &mut diesel::PgConnection,
+        user_id: i32,
+    ) -> Result<User, diesel::result::Error> {
+        diesel::update(users_table::table.find(user_id))
+            .set(users_table::security_stamp.eq(new_security_stamp()))
+            .returning(User::as_returning())
+            .get_result(conn)
+    }
 }
 
 impl AuthUser for User {
@@ -72,8 +288,11 @@ impl AuthUser for User {
     fn id(&self) -> Self::Id {
         self.id
     }
+    /// Sessions store this value and are rejected once it changes. The stamp is rotated on
+    /// every credential change (see the `accounts_credential_change` trigger) and by
+    /// "sign out everywhere", while renaming a user leaves sessions alone.
     fn session_auth_hash(&self) -> &[u8] {
-        self.username.as_bytes()
+        self.security_stamp.as_bytes()
     }
 }
 
@@ -88,17 +307,117 @@ pub struct Credentials {
 pub struct Backend {
     pub db:
         std::sync::Arc<diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>,
+    /// Refuse email/password logins until the address has been verified.
+    /// Set with `REQUIRE_EMAIL_VERIFICATION=true`.
+    pub require_email_verification: bool,
+    /// Who may create an account, see [`SignupMode::from_env`]
+    pub signup_mode: SignupMode,
+    /// Invites each user without the admin role may have at a time, set with
+    /// `INVITE_QUOTA`; admins are not limited
+    pub invite_quota: i64,
 }
 
 impl Backend {
     pub fn new() -> Self {
         let db = crate::db::db_utils::establish_pool();
+        let require_email_verification =
+            std::env::var("REQUIRE_EMAIL_VERIFICATION").is_ok_and(|v| v == "true" || v == "1");
+        let invite_quota = std::env::var("INVITE_QUOTA")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
         Self {
             db: std::sync::Arc::new(db),
+            require_email_verification,
+            signup_mode: SignupMode::from_env(),
+            invite_quota,
         }
     }
 }
 
+impl Backend {
+    /// Resolves the user for a completed OAuth sign-in, linking or creating accounts as needed.
+    ///
+    /// Resolution order:
+    /// 1. an `oauth` account already linked to this provider identity;
+    /// 2. the currently logged-in user (`link_to`), which links the identity to them;
+    /// 3. an existing email account with the same address, only when both the provider
+    ///    and the account itself have verified the address. Linking to an unverified
+    ///    account would let whoever registered the address first, possibly with a
+    ///    password of their choosing, keep access to the account;
+    /// 4. a brand new user, only while signup is open: invite codes are entered on the
+    ///    signup form, which providers bypass.
+    pub async fn authenticate_oauth(
+        &self,
+        identity: OAuthIdentity,
+        link_to: Option<i32>,
+    ) -> Result<User, Error> {
+        let db = self.db.clone();
+        let signup_open = self.signup_mode == SignupMode::Open;
+        let user = task::spawn_blocking(move || {
+            let mut conn = db.get()?;
+            let tokens = OAuthTokens {
+                access_token: Some(identity.access_token.clone()),
+                refresh_token: identity.refresh_token.clone(),
+                expires_at: identity.expires_at,
+                token_type: Some(identity.token_type.clone()),
+                scope: identity.scope.clone(),
+                id_token: identity.id_token.clone(),
+            };
+
+            conn.transaction::<User, Error, _>(|conn| {
+                if let Some(account) =
+                    Account::find_by_provider(conn, &identity.provider, &identity.subject)?
+                {
+                    Account::update_oauth_tokens(conn, account.id, &tokens)?;
+                    return Ok(users_table::table
+                        .find(account.user_id)
+                        .select(User::as_select())
+                        .first(conn)?);
+                }
+
+                let existing_user_id = match link_to {
+                    Some(user_id) => Some(user_id),
+                    None if identity.email_verified => match &identity.email {
+                        Some(email) => match Account::find_by_email(conn, email).optional()? {
+                            Some(account) if account.email_verified.is_none() => {
+                                return Err(Error::UnverifiedAccountExists);
+                            }
+                            account => account.map(|account| account.user_id),
+                        },
+                        None => None,
+                    },
+                    None => None,
+                };
+
+                let user = match existing_user_id {
+                    Some(user_id) => users_table::table
+                        .find(user_id)
+                        .select(User::as_select())
+                        .first(conn)?,
+                    None if !signup_open => return Err(Error::SignupClosed),
+                    None => {
+                        let username =
+                            User::available_username(conn, &identity.suggested_username())?;
+                        User::create(conn, username)?
+                    }
+                };
+
+                Account::create_oauth_account(
+                    conn,
+                    user.id,
+                    identity.provider.clone(),
+                    identity.subject.clone(),
+                    &tokens,
+                )?;
+                Ok(user)
+            })
+        })
+        .await??;
+        Ok(user)
+    }
+}
+
 impl Default for Backend {
     fn default() -> Self {
         Self::new()
@@ -111,6 +430,14 @@ pub enum Error {
     Diesel(#[from] diesel::result::Error),
     #[error(transparent)]
     TaskJoin(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    Pool(#[from] r2d2::Error),
+    #[error("email address has not been verified")]
+    EmailNotVerified,
+    #[error("signing up is not open")]
+    SignupClosed,
+    #[error("an account with an unverified email address uses this address")]
+    UnverifiedAccountExists,
 }
 
 #[async_trait]
@@ -124,7 +451,7 @@ impl AuthnBackend for Backend {
         creds: Self::Credentials,
     ) -> Result<Option<Self::User>, Self::Error> {
         let db = self.db.clone();
-        let email = creds.email.clone();
+        let email = normalize_email(&creds.email);
         let password = creds.password;
 
         let user_and_account: Option<(User, Account)> = task::spawn_blocking(move || {
@@ -137,7 +464,7 @@ impl AuthnBackend for Backend {
                     crate::db::schema::accounts::table
                         .on(crate::db::schema::accounts::user_id.eq(users_table::id)),
                 )
-                .filter(crate::db::schema::accounts::email.eq(&email))
+                .filter(lower(crate::db::schema::accounts::email.assume_not_null()).eq(&email))
                 .filter(crate::db::schema::accounts::type_.eq("email"))
                 .filter(crate::db::schema::accounts::password.is_not_null())
                 .select((User::as_select(), Account::as_select()))
@@ -149,6 +476,10 @@ impl AuthnBackend for Backend {
         if let Some((user, account)) = user_and_account {
             if let Some(stored_password) = account.password {
                 let valid = verify_password(password, &stored_password).is_ok();
+                // Only reveal the verification state to someone who knows the password.
+                if valid && self.require_email_verification && account.email_verified.is_none() {
+                    return Err(Error::EmailNotVerified);
+                }
                 Ok(if valid { Some(user) } else { None })
             } else {
                 Ok(None)
@@ -175,4 +506,201 @@ impl AuthnBackend for Backend {
     }
 }
 
+/// Roles act as axum-login groups: users hold the permissions of every role assigned to them.
+#[async_trait]
+impl AuthzBackend for Backend {
+    type Permission = Permission;
+
+    async fn get_group_permissions(
+        &self,
+        user: &Self::User,
+    ) -> Result<HashSet<Self::Permission>, Self::Error> {
+        let db = self.db.clone();
+        let user_id = user.id;
+        let permissions = task::spawn_blocking(move || {
+            let mut conn = db.get()?;
+            Ok::<_, Error>(Permission::for_user(&mut conn, user_id)?)
+        })
+        .await??;
+        Ok(permissions.into_iter().collect())
+    }
+}
+
 pub type AuthSession = axum_login::AuthSession<Backend>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_db;
+
+    #[test]
+    fn verification_tokens_are_single_use_and_bound_to_their_purpose() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let subject = format!("{}@example.com", test_db::unique("token"));
+        let ttl = chrono::Duration::minutes(5);
+        let purpose = VerificationToken::RESET_PASSWORD;
+
+        let token = VerificationToken::issue(conn, purpose, &subject, ttl).unwrap();
+        let wrong_purpose = VerificationToken::VERIFY_EMAIL;
+        assert_eq!(
+            VerificationToken::peek(conn, wrong_purpose, &token).unwrap(),
+            None
+        );
+        assert_eq!(
+            VerificationToken::consume(conn, wrong_purpose, &token).unwrap(),
+            None
+        );
+
+        assert_eq!(
+            VerificationToken::peek(conn, purpose, &token).unwrap(),
+            Some(subject.clone())
+        );
+        assert_eq!(
+            VerificationToken::consume(conn, purpose, &token).unwrap(),
+            Some(subject.clone())
+        );
+        assert_eq!(
+            VerificationToken::peek(conn, purpose, &token).unwrap(),
+            None
+        );
+        assert_eq!(
+            VerificationToken::consume(conn, purpose, &token).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn expired_verification_tokens_are_refused_and_removed() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let subject = format!("{}@example.com", test_db::unique("expired"));
+        let purpose = VerificationToken::MAGIC_LINK;
+
+        let token =
+            VerificationToken::issue(conn, purpose, &subject, chrono::Duration::seconds(-1))
+                .unwrap();
+        assert_eq!(
+            VerificationToken::peek(conn, purpose, &token).unwrap(),
+            None
+        );
+        assert_eq!(
+            VerificationToken::consume(conn, purpose, &token).unwrap(),
+            None
+        );
+        let left = verification_tokens::table
+            .filter(verification_tokens::identifier.eq(format!("{purpose}:{subject}")))
+            .count()
+            .get_result::<i64>(conn)
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn issuing_a_token_revokes_the_previous_one() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let subject = format!("{}@example.com", test_db::unique("reissue"));
+        let purpose = VerificationToken::VERIFY_EMAIL;
+        let ttl = chrono::Duration::minutes(5);
+
+        let first = VerificationToken::issue(conn, purpose, &subject, ttl).unwrap();
+        let second = VerificationToken::issue(conn, purpose, &subject, ttl).unwrap();
+        assert_eq!(
+            VerificationToken::peek(conn, purpose, &first).unwrap(),
+            None
+        );
+        assert_eq!(
+            VerificationToken::peek(conn, purpose, &second).unwrap(),
+            Some(subject)
+        );
+    }
+
+    #[tokio::test]
+    async fn changing_the_password_invalidates_existing_sessions() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let backend = Backend {
+            db: std::sync::Arc::new(pool),
+            require_email_verification: false,
+            signup_mode: SignupMode::Open,
+            invite_quota: 0,
+        };
+        let email = format!("{}@example.com", test_db::unique("stamp"));
+        let account = {
+            let mut conn = backend.db.get().unwrap();
+            let user = User::create(&mut conn, test_db::unique("stamp")).unwrap();
+            let hash = password_auth::generate_hash("old password");
+            Account::create_email_account(&mut conn, user.id, email.clone(), hash).unwrap()
+        };
+
+        let credentials = Credentials {
+            email,
+            password: "old password".to_string(),
+            next: None,
+        };
+        let signed_in = backend.authenticate(credentials).await.unwrap().unwrap();
+        let session_hash = signed_in.session_auth_hash().to_vec();
+
+        {
+            let mut conn = backend.db.get().unwrap();
+            let hash = password_auth::generate_hash("new password");
+            Account::update_password(&mut conn, account.id, hash).unwrap();
+        }
+        let reloaded = backend.get_user(&signed_in.id).await.unwrap().unwrap();
+        assert_ne!(reloaded.session_auth_hash(), session_hash.as_slice());
+    }
+
+    #[test]
+    fn rotated_security_stamps_look_like_the_column_default() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let user = User::create(conn, test_db::unique("stamp")).unwrap();
+        let rotated = User::rotate_security_stamp(conn, user.id).unwrap();
+        assert_ne!(rotated.security_stamp, user.security_stamp);
+        for stamp in [&user.security_stamp, &rotated.security_stamp] {
+            assert_eq!(stamp.len(), 36, "{stamp}");
+            assert_eq!(stamp.matches('-').count(), 4, "{stamp}");
+        }
+    }
+
+    #[test]
+    fn available_username_adds_a_suffix_to_taken_names() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let base = test_db::unique("taken");
+
+        assert_eq!(User::available_username(conn, &base).unwrap(), base);
+        User::create(conn, base.clone()).unwrap();
+        // Usernames are unique regardless of case.
+        let upper = base.to_uppercase();
+        assert_eq!(
+            User::available_username(conn, &upper).unwrap(),
+            format!("{upper}-2")
+        );
+        User::create(conn, format!("{base}-2")).unwrap();
+        assert_eq!(
+            User::available_username(conn, &base).unwrap(),
+            format!("{base}-3")
+        );
+
+        // Long suggestions are shortened enough to leave room for the suffix.
+        let long = format!("{base}{}", "x".repeat(USERNAME_MAX_LEN));
+        let sanitized = sanitize_username(&long);
+        User::create(conn, sanitized.clone()).unwrap();
+        let suffixed = User::available_username(conn, &long).unwrap();
+        assert_eq!(suffixed, format!("{sanitized}-2"));
+        assert!(crate::validation::validate_username(&suffixed).is_ok());
+    }
+}
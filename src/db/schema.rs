@@ -1,5 +1,11 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::sql_types::SqlType, std::fmt::Debug)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
 diesel::table! {
     accounts (id) {
         id -> Int4,
@@ -17,10 +23,100 @@ diesel::table! {
         id_token -> Nullable<Text>,
         session_state -> Nullable<Text>,
         refresh_token_expires_in -> Nullable<Int4>,
+        email_verified -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Array<Text>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        event -> Text,
+        outcome -> Text,
+        user_id -> Nullable<Int4>,
+        subject -> Nullable<Text>,
+        detail -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
     }
 }
 
 diesel::table! {
+    comments (id) {
+        id -> Int4,
+        post_id -> Int4,
+        parent_id -> Nullable<Int4>,
+        author_id -> Nullable<Int4>,
+        body -> Text,
+        depth -> Int4,
+        created_at -> Timestamp,
+        edited_at -> Nullable<Timestamp>,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    invites (id) {
+        id -> Int4,
+        code_hash -> Text,
+        inviter_id -> Nullable<Int4>,
+        note -> Text,
+        max_uses -> Int4,
+        uses -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    login_attempts (key) {
+        key -> Text,
+        failures -> Int4,
+        last_failure_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    permissions (id) {
+        id -> Int4,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    post_reactions (post_id, user_id, reaction) {
+        post_id -> Int4,
+        user_id -> Int4,
+        reaction -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    post_tags (post_id, tag_id) {
+        post_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     posts (id) {
         id -> Int4,
         author_id -> Nullable<Int4>,
@@ -28,15 +124,64 @@ diesel::table! {
         title -> Text,
         body -> Text,
         published -> Bool,
+        publish_at -> Nullable<Timestamp>,
+        hidden -> Bool,
+        revision -> Int4,
+        body_html -> Nullable<Text>,
+        body_html_revision -> Nullable<Int4>,
+        search_vector -> Nullable<Tsvector>,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    role_permissions (role_id, permission_id) {
+        role_id -> Int4,
+        permission_id -> Int4,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> Int4,
+        name -> Text,
+        description -> Text,
     }
 }
 
 diesel::table! {
     sessions (id) {
         id -> Text,
-        session_token -> Text,
-        user_id -> Int4,
+        user_id -> Nullable<Int4>,
         expires -> Timestamp,
+        data -> Jsonb,
+        created_at -> Timestamp,
+        last_active_at -> Timestamp,
+        ip_address -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Int4,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role_id) {
+        user_id -> Int4,
+        role_id -> Int4,
     }
 }
 
@@ -45,6 +190,9 @@ diesel::table! {
         id -> Int4,
         username -> Text,
         created_at -> Timestamp,
+        totp_secret -> Nullable<Text>,
+        security_stamp -> Text,
+        totp_last_step -> Nullable<Int8>,
     }
 }
 
@@ -57,13 +205,40 @@ diesel::table! {
 }
 
 diesel::joinable!(accounts -> users (user_id));
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(audit_events -> users (user_id));
+diesel::joinable!(comments -> posts (post_id));
+diesel::joinable!(comments -> users (author_id));
+diesel::joinable!(invites -> users (inviter_id));
+diesel::joinable!(post_reactions -> posts (post_id));
+diesel::joinable!(post_reactions -> users (user_id));
+diesel::joinable!(post_tags -> posts (post_id));
+diesel::joinable!(post_tags -> tags (tag_id));
 diesel::joinable!(posts -> users (author_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(role_permissions -> permissions (permission_id));
+diesel::joinable!(role_permissions -> roles (role_id));
 diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(user_roles -> roles (role_id));
+diesel::joinable!(user_roles -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     accounts,
+    api_tokens,
+    audit_events,
+    comments,
+    invites,
+    login_attempts,
+    permissions,
+    post_reactions,
+    post_tags,
     posts,
+    recovery_codes,
+    role_permissions,
+    roles,
     sessions,
+    tags,
+    user_roles,
     users,
     verification_tokens,
 );
@@ -0,0 +1,291 @@
+//! `tower_sessions` store persisting sessions in the `sessions` table.
+//!
+//! Selected with `SESSION_STORE=database`; the default `memory` store keeps sessions in
+//! process, which is fine for development but logs everybody out on restart.
+use crate::db::models::users::Session;
+use crate::db::schema::sessions;
+use crate::session_meta::SessionMeta;
+use async_trait::async_trait;
+use axum_login::tower_sessions::{
+    MemoryStore,
+    cookie::time::OffsetDateTime,
+    session::{Id, Record},
+    session_store::{self, ExpiredDeletion, SessionStore},
+};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use std::sync::Arc;
+use tokio::task;
+
+/// Session key under which `axum-login` keeps the logged-in user id.
+const AUTH_DATA_KEY: &str = "axum-login.data";
+
+#[derive(Clone)]
+pub struct PgSessionStore {
+    db: Arc<Pool<ConnectionManager<PgConnection>>>,
+}
+
+impl std::fmt::Debug for PgSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PgSessionStore").finish_non_exhaustive()
+    }
+}
+
+impl PgSessionStore {
+    pub fn new(db: Arc<Pool<ConnectionManager<PgConnection>>>) -> Self {
+        Self { db }
+    }
+
+    /// Runs `f` with a pooled connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> session_store::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PgConnection) -> QueryResult<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            let mut conn = db
+                .get()
+                .map_err(|e| session_store::Error::Backend(e.to_string()))?;
+            f(&mut conn).map_err(|e| session_store::Error::Backend(e.to_string()))
+        })
+        .await
+        .map_err(|e| session_store::Error::Backend(e.to_string()))?
+    }
+
+    /// Deletes every stored session of `user_id`, e.g. after a password reset.
+    pub async fn delete_for_user(&self, user_id: i32) -> session_store::Result<()> {
+        self.with_conn(move |conn| {
+            diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Live sessions of `user_id`, most recently active first.
+    pub async fn list_for_user(&self, user_id: i32) -> session_store::Result<Vec<Session>> {
+        self.with_conn(move |conn| {
+            sessions::table
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::expires.gt(chrono::Utc::now().naive_utc()))
+                .order(sessions::last_active_at.desc())
+                .select(Session::as_select())
+                .load(conn)
+        })
+        .await
+    }
+
+    /// Spawns a task deleting expired rows every `period`.
+    pub fn spawn_cleanup(&self, period: std::time::Duration) -> task::JoinHandle<()> {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = store.delete_expired().await {
+                    eprintln!("Failed to delete expired sessions: {e}");
+                }
+            }
+        })
+    }
+}
+
+fn to_row(record: &Record) -> session_store::Result<Session> {
+    let user_id = record
+        .data
+        .get(AUTH_DATA_KEY)
+        .and_then(|auth| auth.get("user_id"))
+        .and_then(|id| id.as_i64())
+        .and_then(|id| i32::try_from(id).ok());
+    let meta = record
+        .data
+        .get(SessionMeta::SESSION_KEY)
+        .and_then(|meta| serde_json::from_value::<SessionMeta>(meta.clone()).ok());
+    let data = serde_json::to_value(&record.data)
+        .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+    let now = chrono::Utc::now().naive_utc();
+    let timestamp = |ts: i64| {
+        chrono::DateTime::from_timestamp(ts, 0)
+            .map(|date| date.naive_utc())
+            .unwrap_or(now)
+    };
+
+    Ok(Session {
+        id: record.id.to_string(),
+        user_id,
+        expires: to_naive(record.expiry_date),
+        data,
+        created_at: meta.as_ref().map_or(now, |meta| timestamp(meta.created_at)),
+        last_active_at: meta
+            .as_ref()
+            .map_or(now, |meta| timestamp(meta.last_active_at)),
+        ip_address: meta.as_ref().map(|meta| meta.ip.clone()),
+        user_agent: meta.map(|meta| meta.user_agent),
+    })
+}
+
+fn to_record(row: Session) -> session_store::Result<Record> {
+    let id = row
+        .id
+        .parse::<Id>()
+        .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+    let data = serde_json::from_value(row.data)
+        .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+    let expiry_date = OffsetDateTime::from_unix_timestamp(row.expires.and_utc().timestamp())
+        .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+
+    Ok(Record {
+        id,
+        data,
+        expiry_date,
+    })
+}
+
+fn to_naive(date: OffsetDateTime) -> chrono::NaiveDateTime {
+    chrono::DateTime::from_timestamp(date.unix_timestamp(), 0)
+        .unwrap_or_default()
+        .naive_utc()
+}
+
+#[async_trait]
+impl SessionStore for PgSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        loop {
+            let row = to_row(record)?;
+            let inserted = self
+                .with_conn(move |conn| {
+                    diesel::insert_into(sessions::table)
+                        .values(&row)
+                        .on_conflict_do_nothing()
+                        .execute(conn)
+                })
+                .await?;
+            if inserted == 1 {
+                return Ok(());
+            }
+            // The generated id is already taken, try again with a fresh one.
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let row = to_row(record)?;
+        self.with_conn(move |conn| {
+            diesel::insert_into(sessions::table)
+                .values(&row)
+                .on_conflict(sessions::id)
+                .do_update()
+                .set((
+                    sessions::user_id.eq(row.user_id),
+                    sessions::expires.eq(row.expires),
+                    sessions::data.eq(&row.data),
+                    sessions::last_active_at.eq(row.last_active_at),
+                    sessions::ip_address.eq(&row.ip_address),
+                    sessions::user_agent.eq(&row.user_agent),
+                ))
+                .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let id = session_id.to_string();
+        let row = self
+            .with_conn(move |conn| {
+                sessions::table
+                    .find(id)
+                    .filter(sessions::expires.gt(chrono::Utc::now().naive_utc()))
+                    .select(Session::as_select())
+                    .first(conn)
+                    .optional()
+            })
+            .await?;
+        row.map(to_record).transpose()
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        let id = session_id.to_string();
+        self.with_conn(move |conn| diesel::delete(sessions::table.find(id)).execute(conn))
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for PgSessionStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        self.with_conn(|conn| {
+            diesel::delete(
+                sessions::table.filter(sessions::expires.le(chrono::Utc::now().naive_utc())),
+            )
+            .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Session store picked at startup with the `SESSION_STORE` environment variable.
+#[derive(Debug, Clone)]
+pub enum AppSessionStore {
+    Memory(MemoryStore),
+    Database(PgSessionStore),
+}
+
+impl AppSessionStore {
+    /// `SESSION_STORE=database` persists sessions in Postgres, anything else keeps them in memory.
+    pub fn from_env(db: Arc<Pool<ConnectionManager<PgConnection>>>) -> Self {
+        match std::env::var("SESSION_STORE").as_deref() {
+            Ok("database") => Self::Database(PgSessionStore::new(db)),
+            _ => Self::Memory(MemoryStore::default()),
+        }
+    }
+
+    /// Live sessions of `user_id`, or `None` when the store cannot list them.
+    pub async fn list_for_user(&self, user_id: i32) -> session_store::Result<Option<Vec<Session>>> {
+        match self {
+            Self::Memory(_) => Ok(None),
+            Self::Database(store) => store.list_for_user(user_id).await.map(Some),
+        }
+    }
+
+    /// Deletes every stored session of `user_id` when the store supports it.
+    pub async fn delete_for_user(&self, user_id: i32) -> session_store::Result<()> {
+        match self {
+            Self::Memory(_) => Ok(()),
+            Self::Database(store) => store.delete_for_user(user_id).await,
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for AppSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self {
+            Self::Memory(store) => store.create(record).await,
+            Self::Database(store) => store.create(record).await,
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            Self::Memory(store) => store.save(record).await,
+            Self::Database(store) => store.save(record).await,
+        }
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            Self::Memory(store) => store.load(session_id).await,
+            Self::Database(store) => store.load(session_id).await,
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        match self {
+            Self::Memory(store) => store.delete(session_id).await,
+            Self::Database(store) => store.delete(session_id).await,
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Database access for tests.
+//!
+//! Tests needing Postgres run against `DATABASE_URL` (with every migration applied) on a
+//! single connection whose changes are rolled back when the pool is dropped. They are
+//! skipped when `DATABASE_URL` is not set.
+use super::db_utils::PgPool;
+use diesel::PgConnection;
+use diesel::connection::Connection;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+
+#[derive(Debug)]
+struct TestTransaction;
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for TestTransaction {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.begin_test_transaction()
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// A one-connection pool inside a transaction that is never committed, `None` without a
+/// database to test against
+pub fn pool() -> Option<PgPool> {
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set, skipping database test");
+        return None;
+    };
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(std::time::Duration::from_secs(5))
+        .connection_customizer(Box::new(TestTransaction))
+        .build(ConnectionManager::<PgConnection>::new(database_url))
+        .expect("Failed to connect to the test database");
+    Some(pool)
+}
+
+/// A random suffix keeping usernames and addresses of concurrent tests apart
+pub fn unique(prefix: &str) -> String {
+    format!(
+        "{prefix}{}",
+        &crate::tokens::hash_token(&crate::tokens::generate_token())[..10]
+    )
+}
@@ -1,2 +1,17 @@
+pub mod api_auth;
+pub mod audit;
+pub mod client_info;
+pub mod csrf;
 pub mod db;
+pub mod mailer;
+pub mod markdown;
+pub mod oauth;
+pub mod publishing;
+pub mod redirect;
+pub mod search;
+pub mod session_meta;
 pub mod templates;
+pub mod throttle;
+pub mod tokens;
+pub mod two_factor;
+pub mod validation;
@@ -1,34 +1,114 @@
 use askama::Template;
 use axum::{
-    Extension, Router,
-    extract::{Form, State},
-    http::StatusCode,
-    response::{Html, Redirect},
-    routing::{get, post},
+    Extension, Json, Router,
+    extract::{Form, FromRef, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{delete, get, post},
 };
 use axum_login::{
-    AuthManagerLayerBuilder, AuthnBackend,
-    tower_sessions::{MemoryStore, SessionManagerLayer},
+    AuthManagerLayerBuilder, AuthnBackend, AuthzBackend, login_required, permission_required,
+    tower_sessions::{Session, SessionManagerLayer, SessionStore},
 };
-use password_auth::generate_hash;
+use diesel::Connection;
+use password_auth::{generate_hash, verify_password};
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
 use tower_http::{compression::CompressionLayer, services::ServeDir};
 use z3_app::{
+    api_auth::ApiUser,
+    audit::{self, Audit},
+    client_info::ClientIp,
+    csrf::{self, CsrfToken},
     db::{
-        db_utils,
         models::{
             accounts::Account,
-            posts::{NewPost, Post},
-            users::{AuthSession, Backend, Credentials, User},
+            api_tokens::ApiToken,
+            audit_events::{AuditEvent, AuditFilter},
+            comments::{Comment, CommentDeletion, CommentListing, CommentNode},
+            invites::Invite,
+            personal_data::PersonalData,
+            posts::{NewPost, Post, PostChanges, PostCursor},
+            reactions::{PostReaction, ReactionCounts, Reactions},
+            recovery_codes::RecoveryCode,
+            roles::{Permission, Role},
+            tags::{Tag, TagCount},
+            users::{
+                AuthSession, Backend, Credentials, Error as AuthError, User, VerificationToken,
+            },
         },
+        session_store::AppSessionStore,
     },
+    mailer::{self, Email, Mailer},
+    markdown,
+    oauth::{OAuthProviders, PendingAuthorization},
+    publishing, redirect, search,
+    session_meta::{self, ActiveSession, SessionMeta},
     templates::{
-        ErrorMessageTemplate, LoginFormTemplate, MainTemplate, PostTemplate, RedirectTemplate,
-        SignupFormTemplate, SignupSuccessTemplate, UserHeaderTemplate, WelcomeTemplate,
+        AccountSettingsTemplate, AdminUserRowTemplate, AdminUsersTemplate, ApiTokenCreatedTemplate,
+        ApiTokensTemplate, AuditLogTemplate, CommentBodyTemplate, CommentEditTemplate,
+        CommentFormTemplate, CommentTemplate, DraftRowTemplate, DraftsTemplate,
+        EmailNotVerifiedTemplate, ErrorMessageTemplate, ForgotPasswordTemplate,
+        InfoMessageTemplate, InviteCreatedTemplate, InvitesTemplate, LoginFormTemplate,
+        MagicLinkTemplate, MainTemplate, ModerationTemplate, PasswordResetSuccessTemplate,
+        PostDraftSavedTemplate, PostEditTemplate, PostPageTemplate, PostReactionsTemplate,
+        PostTemplate, PostsMoreTemplate, RecoveryCodesTemplate, RedirectTemplate,
+        ResetPasswordTemplate, SearchResultsTemplate, SearchTemplate, SessionsTemplate,
+        SignupFormTemplate, SignupSuccessTemplate, TagTemplate, TagsTemplate,
+        TwoFactorLoginTemplate, TwoFactorSettingsTemplate, UserHeaderTemplate, VerifyEmailTemplate,
+        WelcomeTemplate,
     },
+    throttle::{self, LoginThrottle},
+    two_factor::{self, PendingLogin},
+    validation::{self, SignupErrors},
 };
 
+/// Shared state handed to every handler.
+#[derive(Clone)]
+struct AppState {
+    backend: Backend,
+    oauth: Arc<OAuthProviders>,
+    mailer: Arc<dyn Mailer>,
+    sessions: AppSessionStore,
+    throttle: LoginThrottle,
+}
+
+impl FromRef<AppState> for Backend {
+    fn from_ref(state: &AppState) -> Self {
+        state.backend.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<OAuthProviders> {
+    fn from_ref(state: &AppState) -> Self {
+        state.oauth.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn Mailer> {
+    fn from_ref(state: &AppState) -> Self {
+        state.mailer.clone()
+    }
+}
+
+impl FromRef<AppState> for AppSessionStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.sessions.clone()
+    }
+}
+
+impl FromRef<AppState> for LoginThrottle {
+    fn from_ref(state: &AppState) -> Self {
+        state.throttle.clone()
+    }
+}
+
+/// How often expired rows are purged from the database session and login attempt stores
+const SESSION_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(300);
+/// How often scheduled drafts are checked for publishing
+const SCHEDULED_PUBLISHING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
 /// Launches the Axum web server with HTML template rendering and static file serving.
 ///
 /// Sets up application routes for the root path (`/`), a test page (`/test`), and static file serving at `/static`.
@@ -43,26 +123,133 @@ use z3_app::{
 #[tokio::main]
 async fn main() {
     let backend = Backend::new();
-    let store = MemoryStore::default();
-    let session_layer = SessionManagerLayer::new(store);
+    let store = AppSessionStore::from_env(backend.db.clone());
+    if let AppSessionStore::Database(db_store) = &store {
+        db_store.spawn_cleanup(SESSION_CLEANUP_INTERVAL);
+    }
+    let session_layer = SessionManagerLayer::new(store.clone());
+    let throttle = LoginThrottle::from_env(backend.db.clone());
+    throttle.spawn_cleanup(SESSION_CLEANUP_INTERVAL);
+    publishing::spawn_scheduled_publishing(backend.db.clone(), SCHEDULED_PUBLISHING_INTERVAL);
     let auth_layer = AuthManagerLayerBuilder::new(backend.clone(), session_layer);
+    let state = AppState {
+        backend,
+        oauth: Arc::new(OAuthProviders::from_env().await),
+        mailer: mailer::from_env(),
+        sessions: store,
+        throttle,
+    };
+    // Pages that send anonymous visitors to the login form, coming back afterwards.
+    let protected = Router::new()
+        .route("/drafts", get(drafts_page))
+        .route("/search", get(search_page))
+        .route("/settings/2fa", get(two_factor_settings))
+        .route("/settings/tokens", get(api_tokens_page))
+        .route("/settings/invites", get(invites_page))
+        .route("/settings/sessions", get(sessions_page))
+        .route("/settings/account", get(account_settings))
+        .route("/settings/account/export", get(account_export))
+        .route_layer(login_required!(Backend, login_url = "/login"));
+    // Permission guards answer 403 to logged-in users without the permission; the login
+    // guard is added last so that it runs first and still redirects anonymous visitors.
+    let admin = Router::new()
+        .route("/admin/users", get(admin_users))
+        .route("/admin/audit", get(admin_audit))
+        .route("/admin/audit/export", get(admin_audit_export))
+        .route(
+            "/admin/users/{user_id}/roles/{role}",
+            post(admin_toggle_role),
+        )
+        .route_layer(permission_required!(Backend, Permission::ADMIN))
+        .route_layer(login_required!(Backend, login_url = "/login"));
+    let moderation = Router::new()
+        .route("/moderation", get(moderation_posts))
+        .route(
+            "/moderation/posts/{post_id}/hide",
+            post(moderation_hide_post),
+        )
+        .route_layer(permission_required!(Backend, Permission::MODERATE))
+        .route_layer(login_required!(Backend, login_url = "/login"));
     let app = Router::new()
+        .merge(protected)
+        .merge(admin)
+        .merge(moderation)
         .route("/", get(root))
         .route("/posts", get(post_get))
         .route("/posts", post(post_post))
+        .route("/posts/preview", post(post_preview))
+        .route(
+            "/posts/{post_id}",
+            get(post_show)
+                .put(post_put)
+                .patch(post_patch)
+                .delete(post_delete),
+        )
+        .route("/posts/{post_id}/edit", get(post_edit_form))
+        .route("/posts/{post_id}/publish", post(post_publish))
+        .route("/posts/{post_id}/unpublish", post(post_unpublish))
+        .route("/posts/{post_id}/schedule", post(post_schedule))
+        .route("/posts/{post_id}/comments", post(comment_create))
+        .route("/posts/{post_id}/reactions/{reaction}", post(post_react))
+        .route(
+            "/comments/{comment_id}",
+            get(comment_show).put(comment_put).delete(comment_delete),
+        )
+        .route("/comments/{comment_id}/edit", get(comment_edit_form))
+        .route("/comments/{comment_id}/reply", get(comment_reply_form))
+        .route("/tags", get(tags_page))
+        .route("/tags/{name}", get(tag_page))
         .route("/signup", get(signup_form).post(signup_post))
         .route("/login", get(login_form).post(login_post))
+        .route(
+            "/login/magic",
+            get(magic_link_form).post(magic_link_request),
+        )
+        .route("/login/magic/confirm", post(magic_link_confirm))
+        .route(
+            "/login/2fa",
+            get(two_factor_login_form).post(two_factor_login_post),
+        )
+        .route("/settings/2fa/enable", post(two_factor_enable))
+        .route("/settings/2fa/disable", post(two_factor_disable))
+        .route("/settings/account/delete", post(account_delete))
+        .route("/settings/sessions/{handle}", delete(session_revoke))
+        .route(
+            "/settings/sessions/revoke-others",
+            post(sessions_revoke_others),
+        )
+        .route("/settings/tokens", post(api_token_create))
+        .route("/settings/tokens/{token_id}", delete(api_token_revoke))
+        .route("/settings/invites", post(invite_create))
+        .route("/settings/invites/{invite_id}", delete(invite_revoke))
+        .route("/api/posts", get(api_posts_list).post(api_posts_create))
         .route("/signout", post(signout_post))
+        .route("/signout/everywhere", post(signout_everywhere_post))
+        .route("/verify", get(verify_email))
+        .route("/verify/resend", post(verify_resend))
+        .route(
+            "/forgot-password",
+            get(forgot_password_form).post(forgot_password_post),
+        )
+        .route(
+            "/reset-password",
+            get(reset_password_form).post(reset_password_post),
+        )
+        .route("/auth/{provider}", get(oauth_start))
+        .route("/auth/{provider}/callback", get(oauth_callback))
         .nest_service("/static", ServeDir::new("static"))
+        .layer(middleware::from_fn(session_meta::track_session))
+        .layer(middleware::from_fn(csrf::verify))
         .layer(CompressionLayer::new())
         .layer(auth_layer.build())
-        .with_state(backend);
+        .with_state(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     println!("Listening on http://{addr}");
 
     let listener: tokio::net::TcpListener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    if let Err(e) = axum::serve(listener, app).await {
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    if let Err(e) = axum::serve(listener, service).await {
         eprintln!("Server error: {e}");
         std::process::exit(1);
     }
@@ -79,14 +266,24 @@ async fn main() {
 /// let response = root().await;
 /// assert!(response.0.contains("<html"));
 /// ```
-async fn root(Extension(session): Extension<AuthSession>) -> Html<String> {
+async fn root(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+) -> Html<String> {
     if let Some(user) = &session.user {
         // User is authenticated - show the main app
         let template: MainTemplate = MainTemplate {
-            posts: Post::get_published().await,
+            csrf_token: csrf_token.clone(),
         };
+        let can_admin = has_permission(&session, Permission::ADMIN).await;
         let user_header = UserHeaderTemplate {
+            csrf_token,
             username: user.username.clone(),
+            can_admin,
+            can_moderate: has_permission(&session, Permission::MODERATE).await,
+            can_invite: backend.signup_mode.requires_invite()
+                && (can_admin || backend.invite_quota > 0),
         };
         let mut template_content = user_header.render().unwrap();
         template_content.push_str(&template.render().unwrap());
@@ -98,27 +295,121 @@ async fn root(Extension(session): Extension<AuthSession>) -> Html<String> {
     }
 }
 
-/// Handles GET requests to the `/posts` route by rendering a list of posts.
+/// Whether the logged-in user holds `permission`; anonymous users hold none.
+///
+/// Used by handlers to decide which actions to show, route guards use
+/// `permission_required!` instead.
+async fn has_permission(auth_session: &AuthSession, permission: &str) -> bool {
+    match &auth_session.user {
+        Some(user) => auth_session
+            .backend
+            .has_perm(user, permission.into())
+            .await
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+/// How many posts a page of the feed holds
+const POSTS_PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct PostsQuery {
+    /// Where the page starts, from the previous page; the newest posts when missing
+    pub cursor: Option<String>,
+    /// Only posts with this tag
+    pub tag: Option<String>,
+}
+
+impl PostsQuery {
+    /// The decoded cursor, `Err` when one was given but is malformed
+    fn cursor(&self) -> Result<Option<PostCursor>, ()> {
+        match self.cursor.as_deref() {
+            None | Some("") => Ok(None),
+            Some(cursor) => PostCursor::decode(cursor).map(Some).ok_or(()),
+        }
+    }
+
+    /// URL of the feed page after `next`, keeping the tag filter
+    fn next_url(&self, next: &PostCursor) -> String {
+        let mut params = vec![("cursor", next.encode())];
+        if let Some(tag) = &self.tag {
+            params.push(("tag", tag.clone()));
+        }
+        format!(
+            "/posts?{}",
+            serde_urlencoded::to_string(params).unwrap_or_default()
+        )
+    }
+}
+
+/// Handles GET requests to the `/posts` route by rendering a page of the feed.
 ///
-/// Returns multiple rendered `PostTemplate` as one HTML response.
+/// Returns multiple rendered `PostTemplate` as one HTML response, followed by a
+/// `PostsMoreTemplate` loading the next page when there is one.
 ///
 /// # Examples
 /// /// ```
 /// // In an Axum application, this handler can be used as follows:
 /// let app = axum::Router::new().route("/posts", get(post_get));
 /// ```
-async fn post_get(Extension(session): Extension<AuthSession>) -> Result<Html<String>, StatusCode> {
+async fn post_get(
+    Extension(session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Query(query): Query<PostsQuery>,
+) -> Result<Html<String>, StatusCode> {
     // Check if user is authenticated
     if session.user.is_none() {
         return Err(StatusCode::UNAUTHORIZED);
     }
 
-    let posts: Vec<Post> = Post::get_published().await;
+    let cursor = query.cursor().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = match query.tag.as_deref() {
+        Some(tag) => {
+            let tag = validation::normalize_tag(tag)
+                .map(|name| Tag::find_by_name(&mut conn, &name))
+                .transpose()
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .flatten()
+                .ok_or(StatusCode::NOT_FOUND)?;
+            Post::get_published_with_tag(&mut conn, tag.id, cursor, POSTS_PAGE_SIZE)
+        }
+        None => Post::get_published(&mut conn, cursor, POSTS_PAGE_SIZE),
+    }
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post_ids: Vec<i32> = page.posts.iter().map(|post| post.id).collect();
+    let mut tags = Tag::names_for_posts(&mut conn, &post_ids)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     let mut html = String::new();
+    let can_moderate = has_permission(&session, Permission::MODERATE).await;
+    let user_id = session
+        .user
+        .as_ref()
+        .map(|user| user.id)
+        .unwrap_or_default();
+    let given = PostReaction::given_by(&mut conn, user_id, &post_ids)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    for post in posts {
-        let post_template: PostTemplate = PostTemplate { post };
-        html.push_str(&post_template.render().unwrap());
+    for post in page.posts {
+        let post_tags = tags.remove(&post.id).unwrap_or_default();
+        let reactions = Reactions::new(post.id, &page.reactions, &given);
+        html.push_str(&render_post(
+            post,
+            post_tags,
+            reactions,
+            user_id,
+            can_moderate,
+        ));
+    }
+    if let Some(next) = page.next {
+        let more_template = PostsMoreTemplate {
+            url: query.next_url(&next),
+        };
+        html.push_str(&more_template.render().unwrap());
     }
 
     Ok(Html(html))
@@ -128,10 +419,59 @@ async fn post_get(Extension(session): Extension<AuthSession>) -> Result<Html<Str
 pub struct PostForm {
     pub title: String,
     pub body: String,
+    /// Sent by the "Save draft" button of the new post form
+    #[serde(default)]
+    pub draft: bool,
+    /// When to publish the new post, empty to publish it now
+    #[serde(default)]
+    pub publish_at: String,
+    /// Comma separated tags, see [`validation::parse_tags`]
+    #[serde(default)]
+    pub tags: String,
+}
+
+/// Renders `post` as its viewer sees it: a feed item once published, a row of the drafts
+/// page otherwise
+fn render_post(
+    post: Post,
+    tags: Vec<String>,
+    reactions: Reactions,
+    user_id: i32,
+    can_moderate: bool,
+) -> String {
+    if post.published {
+        let template = PostTemplate {
+            can_edit: can_moderate || post.is_authored_by(user_id),
+            is_author: post.is_authored_by(user_id),
+            post,
+            tags,
+            reactions,
+        };
+        template.render().unwrap()
+    } else {
+        let template = DraftRowTemplate {
+            post,
+            error: String::new(),
+        };
+        template.render().unwrap()
+    }
+}
+
+/// Parses the value of a `datetime-local` input, taken as UTC, which must be in the future
+fn parse_publish_at(value: &str) -> Result<chrono::NaiveDateTime, &'static str> {
+    let publish_at = chrono::NaiveDateTime::parse_from_str(value.trim(), "%Y-%m-%dT%H:%M")
+        .map_err(|_| "Invalid publishing time.")?;
+    if publish_at <= chrono::Utc::now().naive_utc() {
+        return Err("The publishing time must be in the future.");
+    }
+    Ok(publish_at)
 }
 
 /// Handles POST requests to the `/posts` route by creating a new post and returning the post as HTML.
 ///
+/// Drafts and scheduled posts are not shown in the feed, a notice linking to the drafts page
+/// is returned for them instead.
+///
 /// # Examples
 ///
 /// ```
@@ -140,6 +480,7 @@ pub struct PostForm {
 /// ```
 async fn post_post(
     Extension(session): Extension<AuthSession>,
+    State(backend): State<Backend>,
     Form(input): Form<PostForm>,
 ) -> Result<Html<String>, StatusCode> {
     println!("Received post input: {input:?}");
@@ -147,6 +488,7 @@ async fn post_post(
     if input.title.is_empty() || input.body.is_empty() {
         return Err(StatusCode::BAD_REQUEST);
     }
+    let tags = validation::parse_tags(&input.tags).map_err(|_| StatusCode::BAD_REQUEST)?;
 
     // Get the current user
     let user = session.user;
@@ -156,118 +498,2938 @@ async fn post_post(
 
     let user = user.unwrap();
 
+    let publish_at = match input.publish_at.trim() {
+        "" => None,
+        value => Some(parse_publish_at(value).map_err(|_| StatusCode::BAD_REQUEST)?),
+    };
+    let published = !input.draft && publish_at.is_none();
+
     let new_post: NewPost = NewPost {
         title: input.title,
         body: input.body,
-        published: Some(true),
+        published: Some(published),
         author_id: Some(user.id),
         created_at: chrono::Utc::now().naive_utc(),
+        publish_at,
     };
 
-    let pool = db_utils::establish_pool();
-    let mut conn = pool.get().unwrap();
-    match Post::create(
-        &mut conn,
-        &new_post.title,
-        &new_post.body,
-        &new_post.author_id,
-        new_post.created_at,
-    ) {
-        Some(post) => {
-            let post_template = PostTemplate { post };
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // A post is only created along with its tags.
+    let created = conn.transaction::<Post, diesel::result::Error, _>(|conn| {
+        let post = Post::create(
+            conn,
+            &new_post.title,
+            &new_post.body,
+            &new_post.author_id,
+            new_post.created_at,
+            published,
+            new_post.publish_at,
+        )
+        .ok_or(diesel::result::Error::RollbackTransaction)?;
+        Tag::set_for_post(conn, post.id, &tags)?;
+        Ok(post)
+    });
+    match created {
+        Ok(post) if post.published => {
+            let post_template = PostTemplate {
+                reactions: Reactions::new(post.id, &ReactionCounts::new(), &HashSet::new()),
+                post,
+                tags,
+                can_edit: true,
+                is_author: true,
+            };
             let html = post_template.render().unwrap();
             Ok(Html(html))
         }
-        None => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Ok(post) => {
+            let saved_template = PostDraftSavedTemplate {
+                title: post.title,
+                publish_at: post.publish_at,
+            };
+            Ok(Html(saved_template.render().unwrap()))
+        }
+        Err(e) => {
+            eprintln!("Failed to create post: {e}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
-/// Renders the signup form
-async fn signup_form() -> Html<String> {
-    let template = SignupFormTemplate {};
-    Html(template.render().unwrap())
+/// Loads a post the signed-in user wants to change.
+///
+/// Answers 404 for unknown posts and 403 unless the user wrote the post or is a moderator.
+async fn editable_post(
+    auth_session: &AuthSession,
+    conn: &mut diesel::PgConnection,
+    post_id: i32,
+) -> Result<Post, StatusCode> {
+    let user = auth_session.user.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+    let post = Post::find(conn, post_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if post.is_authored_by(user.id) || has_permission(auth_session, Permission::MODERATE).await {
+        Ok(post)
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
 }
 
 #[derive(Debug, Deserialize)]
-pub struct SignupForm {
-    pub username: String,
-    pub password: String,
-    pub email: String,
+pub struct PostPreviewForm {
+    #[serde(default)]
+    pub body: String,
+}
+
+/// Renders a Markdown body the way it will be shown, for the live preview of the post forms
+async fn post_preview(
+    Extension(auth_session): Extension<AuthSession>,
+    Form(input): Form<PostPreviewForm>,
+) -> Result<Html<String>, StatusCode> {
+    if auth_session.user.is_none() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(Html(markdown::render(&input.body)))
 }
 
-/// Handles signup POST, creates a new user
-#[axum::debug_handler]
-async fn signup_post(Form(input): Form<SignupForm>) -> Result<Html<String>, StatusCode> {
-    let pool = db_utils::establish_pool();
-    let mut conn = pool.get().unwrap();
-    let hashed: String = generate_hash(&input.password);
+/// Loads a post of the signed-in user, answering 404 for unknown posts and 403 for posts of
+/// other users.
+async fn owned_post(
+    auth_session: &AuthSession,
+    conn: &mut diesel::PgConnection,
+    post_id: i32,
+) -> Result<Post, StatusCode> {
+    let user = auth_session.user.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+    let post = Post::find(conn, post_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if post.is_authored_by(user.id) {
+        Ok(post)
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
 
-    // Create user first
-    let user_result = User::create(&mut conn, input.username);
+/// Loads a post the signed-in user may see.
+///
+/// Unpublished posts are only visible to their author and to moderators; for everyone else
+/// they answer 404 like unknown posts.
+async fn visible_post(
+    auth_session: &AuthSession,
+    conn: &mut diesel::PgConnection,
+    post_id: i32,
+) -> Result<Post, StatusCode> {
+    let user = auth_session.user.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+    let post = Post::find(conn, post_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if post.published
+        || post.is_authored_by(user.id)
+        || has_permission(auth_session, Permission::MODERATE).await
+    {
+        Ok(post)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
 
-    match user_result {
-        Ok(user) => {
-            // Create associated email account
-            let account_result =
-                Account::create_email_account(&mut conn, user.id, input.email, hashed);
-
-            match account_result {
-                Ok(_) => {
-                    let success_template = SignupSuccessTemplate {};
-                    Ok(Html(success_template.render().unwrap()))
-                }
-                Err(e) => {
-                    println!("Failed to create account: {e}");
-                    let error_template = ErrorMessageTemplate {
-                        message: "Failed to create account. Email might already be in use."
-                            .to_string(),
-                    };
-                    Ok(Html(error_template.render().unwrap()))
-                }
+/// Shows a post. Unpublished posts are only shown to those who may edit them.
+///
+/// htmx requests, such as cancelling an edit, get the feed item only; other requests get
+/// the post's page with its comments.
+async fn post_show(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    headers: HeaderMap,
+    Path(post_id): Path<i32>,
+) -> Result<Response, StatusCode> {
+    let is_htmx = headers.contains_key("hx-request");
+    let Some(user) = auth_session.user.as_ref() else {
+        if is_htmx {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        let next = serde_urlencoded::to_string([("next", format!("/posts/{post_id}"))])
+            .unwrap_or_default();
+        return Ok(Redirect::to(&format!("/login?{next}")).into_response());
+    };
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post = Post::find(&mut conn, post_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let can_moderate = has_permission(&auth_session, Permission::MODERATE).await;
+    if !post.published && !post.is_authored_by(user.id) && !can_moderate {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let tags =
+        Tag::names_for_post(&mut conn, post.id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let reactions = Reactions::load(&mut conn, post.id, user.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if is_htmx {
+        let html = render_post(post, tags, reactions, user.id, can_moderate);
+        return Ok(Html(html).into_response());
+    }
+
+    let comments = Comment::thread_for_post(&mut conn, post.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let comment_form = CommentFormTemplate {
+        post_id: post.id,
+        parent_id: None,
+    };
+    let template = PostPageTemplate {
+        csrf_token,
+        title: post.title.clone(),
+        comments_html: render_comments(comments, user.id, can_moderate),
+        comment_form_html: comment_form.render().unwrap(),
+        post_html: render_post(post, tags, reactions, user.id, can_moderate),
+    };
+    Ok(Html(template.render().unwrap()).into_response())
+}
+
+/// Renders comment threads, replies nested under their parent
+fn render_comments(nodes: Vec<CommentNode>, user_id: i32, can_moderate: bool) -> String {
+    let mut html = String::new();
+    for node in nodes {
+        let comment = &node.listing.comment;
+        let template = CommentTemplate {
+            can_edit: !comment.is_deleted() && (can_moderate || comment.is_authored_by(user_id)),
+            can_reply: comment.accepts_replies(),
+            replies: render_comments(node.replies, user_id, can_moderate),
+            listing: node.listing,
+        };
+        html.push_str(&template.render().unwrap());
+    }
+    html
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommentForm {
+    pub body: String,
+    /// Comment replied to, missing for top-level comments
+    pub parent_id: Option<i32>,
+}
+
+/// Adds a comment to a post the user can see, or a reply to one of its comments
+async fn comment_create(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+    Form(input): Form<CommentForm>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+    let body = validation::validate_comment(&input.body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post = visible_post(&auth_session, &mut conn, post_id).await?;
+    let parent = match input.parent_id {
+        Some(parent_id) => {
+            let parent = Comment::find(&mut conn, parent_id)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .filter(|parent| parent.post_id == post.id)
+                .ok_or(StatusCode::NOT_FOUND)?;
+            if !parent.accepts_replies() {
+                return Err(StatusCode::BAD_REQUEST);
             }
+            Some(parent)
         }
-        Err(e) => {
-            println!("Failed to create user: {e}");
-            let error_template = ErrorMessageTemplate {
-                message: "Failed to create user. Username might already be taken.".to_string(),
+        None => None,
+    };
+
+    let comment = Comment::create(&mut conn, post.id, parent.as_ref(), user.id, body)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let template = CommentTemplate {
+        can_edit: true,
+        can_reply: comment.accepts_replies(),
+        replies: String::new(),
+        listing: CommentListing {
+            comment,
+            author: Some(user.username.clone()),
+        },
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Fetches a comment the user may change: their own, or any comment for moderators.
+///
+/// Tombstones of deleted comments cannot be changed.
+async fn editable_comment(
+    auth_session: &AuthSession,
+    conn: &mut diesel::PgConnection,
+    comment_id: i32,
+) -> Result<Comment, StatusCode> {
+    let user = auth_session.user.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+    let comment = Comment::find(conn, comment_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|comment| !comment.is_deleted())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if comment.is_authored_by(user.id) || has_permission(auth_session, Permission::MODERATE).await {
+        Ok(comment)
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Renders the content of a comment, as shown after an edit.
+///
+/// Comments of posts the user cannot see answer 404.
+async fn render_comment_body(
+    auth_session: &AuthSession,
+    conn: &mut diesel::PgConnection,
+    comment_id: i32,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+    let listing = Comment::find_listing(conn, comment_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    visible_post(auth_session, conn, listing.comment.post_id).await?;
+    let template = CommentBodyTemplate {
+        can_edit: !listing.comment.is_deleted()
+            && (listing.comment.is_authored_by(user.id)
+                || has_permission(auth_session, Permission::MODERATE).await),
+        can_reply: listing.comment.accepts_replies(),
+        listing,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+async fn comment_show(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(comment_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    render_comment_body(&auth_session, &mut conn, comment_id).await
+}
+
+async fn comment_reply_form(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(comment_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let parent = Comment::find(&mut conn, comment_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    visible_post(&auth_session, &mut conn, parent.post_id).await?;
+    if !parent.accepts_replies() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let template = CommentFormTemplate {
+        post_id: parent.post_id,
+        parent_id: Some(parent.id),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+async fn comment_edit_form(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(comment_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let comment = editable_comment(&auth_session, &mut conn, comment_id).await?;
+    let template = CommentEditTemplate {
+        comment_id: comment.id,
+        body: comment.body,
+        error: String::new(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommentEditForm {
+    pub body: String,
+}
+
+async fn comment_put(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(comment_id): Path<i32>,
+    Form(input): Form<CommentEditForm>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let comment = editable_comment(&auth_session, &mut conn, comment_id).await?;
+    let body = match validation::validate_comment(&input.body) {
+        Ok(body) => body,
+        Err(error) => {
+            let template = CommentEditTemplate {
+                comment_id: comment.id,
+                body: input.body,
+                error: error.to_string(),
             };
-            Ok(Html(error_template.render().unwrap()))
+            return Ok(Html(template.render().unwrap()));
+        }
+    };
+    Comment::update(&mut conn, comment.id, body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    render_comment_body(&auth_session, &mut conn, comment.id).await
+}
+
+/// Deletes a comment, leaving a tombstone in place when others replied to it
+async fn comment_delete(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(comment_id): Path<i32>,
+) -> Result<Response, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let comment = editable_comment(&auth_session, &mut conn, comment_id).await?;
+    match Comment::delete(&mut conn, comment.id) {
+        Ok(CommentDeletion::Tombstoned) => {
+            let body = render_comment_body(&auth_session, &mut conn, comment.id).await?;
+            Ok(body.into_response())
         }
+        // Take the comment out of the thread along with the tombstones it leaves empty.
+        Ok(CommentDeletion::Removed { outermost_id }) => Ok((
+            [("HX-Retarget", format!("#comment-{outermost_id}"))],
+            Html(String::new()),
+        )
+            .into_response()),
+        Err(diesel::result::Error::NotFound) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
-/// Renders the login form
-async fn login_form() -> Html<String> {
-    let template = LoginFormTemplate {};
-    Html(template.render().unwrap())
+/// Renders the inline edit form swapped in place of a post
+async fn post_edit_form(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post = editable_post(&auth_session, &mut conn, post_id).await?;
+    let tags =
+        Tag::names_for_post(&mut conn, post.id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let template = PostEditTemplate {
+        post_id: post.id,
+        title: post.title,
+        body: post.body,
+        tags: tags.join(", "),
+        error: String::new(),
+    };
+    Ok(Html(template.render().unwrap()))
 }
 
-/// Handles login POST, authenticates user and starts session
-#[axum::debug_handler]
-async fn login_post(
-    Extension(mut session): Extension<AuthSession>,
+#[derive(Debug, Deserialize)]
+pub struct PostPatchForm {
+    pub title: Option<String>,
+    pub body: Option<String>,
+    /// Comma separated, replacing every tag of the post
+    pub tags: Option<String>,
+}
+
+/// Replaces the title and body of a post
+async fn post_put(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+    Form(input): Form<PostForm>,
+) -> Result<Html<String>, StatusCode> {
+    let changes = PostChanges {
+        title: Some(input.title),
+        body: Some(input.body),
+    };
+    update_post(&auth_session, &backend, post_id, changes, Some(input.tags)).await
+}
+
+/// Changes only the fields sent, leaving the others as they are
+async fn post_patch(
+    Extension(auth_session): Extension<AuthSession>,
     State(backend): State<Backend>,
-    Form(input): Form<Credentials>,
+    Path(post_id): Path<i32>,
+    Form(input): Form<PostPatchForm>,
 ) -> Result<Html<String>, StatusCode> {
-    match backend.authenticate(input.clone()).await {
-        Ok(Some(user)) => {
-            session.login(&user).await.unwrap();
-            let redirect_template = RedirectTemplate {
-                redirect_url: "/".to_string(),
-            };
-            Ok(Html(redirect_template.render().unwrap()))
+    let changes = PostChanges {
+        title: input.title,
+        body: input.body,
+    };
+    update_post(&auth_session, &backend, post_id, changes, input.tags).await
+}
+
+/// Applies `changes` to a post and swaps the updated post in place.
+///
+/// Empty fields send the edit form back with an error instead.
+async fn update_post(
+    auth_session: &AuthSession,
+    backend: &Backend,
+    post_id: i32,
+    changes: PostChanges,
+    tags: Option<String>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post = editable_post(auth_session, &mut conn, post_id).await?;
+
+    let changes = PostChanges {
+        title: changes.title.map(|title| title.trim().to_string()),
+        body: changes.body.map(|body| body.trim().to_string()),
+    };
+    let parsed_tags = tags.as_deref().map(validation::parse_tags).transpose();
+    let error = if changes.title.as_deref() == Some("") || changes.body.as_deref() == Some("") {
+        Some("Title and body cannot be empty.")
+    } else {
+        parsed_tags.as_ref().err().copied()
+    };
+    if let Some(error) = error {
+        let tags = match tags {
+            Some(tags) => tags,
+            None => Tag::names_for_post(&mut conn, post.id)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .join(", "),
+        };
+        let template = PostEditTemplate {
+            post_id: post.id,
+            title: changes.title.unwrap_or(post.title),
+            body: changes.body.unwrap_or(post.body),
+            tags,
+            error: error.to_string(),
+        };
+        return Ok(Html(template.render().unwrap()));
+    }
+    // The tags only change along with the rest of the post.
+    let post = conn
+        .transaction::<Post, diesel::result::Error, _>(|conn| {
+            if let Ok(Some(tags)) = &parsed_tags {
+                Tag::set_for_post(conn, post.id, tags)?;
+            }
+            if changes.title.is_none() && changes.body.is_none() {
+                Ok(post)
+            } else {
+                Post::update(conn, post.id, &changes)
+            }
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user_id = auth_session
+        .user
+        .as_ref()
+        .map(|user| user.id)
+        .unwrap_or_default();
+    let can_moderate = has_permission(auth_session, Permission::MODERATE).await;
+    let tags =
+        Tag::names_for_post(&mut conn, post.id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let reactions = Reactions::load(&mut conn, post.id, user_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Html(render_post(
+        post,
+        tags,
+        reactions,
+        user_id,
+        can_moderate,
+    )))
+}
+
+/// Gives or takes back one of the user's reactions to a published post, answering with
+/// the updated reaction buttons
+async fn post_react(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path((post_id, reaction)): Path<(i32, String)>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+    if !PostReaction::is_kind(&reaction) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Post::find(&mut conn, post_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|post| post.published)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    PostReaction::toggle(&mut conn, post_id, user.id, &reaction)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let template = PostReactionsTemplate {
+        reactions: Reactions::load(&mut conn, post_id, user.id)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Deletes a post, removing it from the list
+async fn post_delete(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post = editable_post(&auth_session, &mut conn, post_id).await?;
+    match Post::delete(&mut conn, post.id) {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => Ok(Html(String::new())),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// How many results a page of search results holds
+const SEARCH_PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    /// 1-based page of results
+    pub page: Option<i64>,
+}
+
+/// Searches published posts.
+///
+/// htmx requests, sent as the query is typed and when scrolling, get the results only;
+/// other requests get the whole page.
+async fn search_page(
+    State(backend): State<Backend>,
+    headers: HeaderMap,
+    Query(query): Query<SearchQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let page = query.page.unwrap_or(1).max(1);
+    let (hits, more_url) = match search::to_tsquery(&query.q) {
+        Some(tsquery) => {
+            let mut conn = backend
+                .db
+                .get()
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let mut hits = Post::search(
+                &mut conn,
+                &tsquery,
+                SEARCH_PAGE_SIZE + 1,
+                (page - 1) * SEARCH_PAGE_SIZE,
+            )
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let more_url = (hits.len() as i64 > SEARCH_PAGE_SIZE).then(|| {
+                let next = serde_urlencoded::to_string([
+                    ("q", query.q.as_str()),
+                    ("page", &(page + 1).to_string()),
+                ])
+                .unwrap_or_default();
+                format!("/search?{next}")
+            });
+            hits.truncate(SEARCH_PAGE_SIZE as usize);
+            (hits, more_url)
         }
-        _ => {
-            let error_template = ErrorMessageTemplate {
-                message: "Invalid email or password. Please try again.".to_string(),
+        None => (Vec::new(), None),
+    };
+
+    let html = if headers.contains_key("hx-request") {
+        let template = SearchResultsTemplate {
+            q: query.q,
+            hits,
+            more_url,
+            first_page: page == 1,
+        };
+        template.render().unwrap()
+    } else {
+        let template = SearchTemplate {
+            q: query.q,
+            hits,
+            more_url,
+            first_page: page == 1,
+        };
+        template.render().unwrap()
+    };
+    Ok(Html(html))
+}
+
+/// How many tags the tag cloud shows
+const TAG_CLOUD_SIZE: i64 = 100;
+
+/// The tag cloud, each tag with a text size class growing with its share of posts
+fn tag_cloud(
+    conn: &mut diesel::PgConnection,
+) -> Result<Vec<(TagCount, &'static str)>, diesel::result::Error> {
+    let cloud = Tag::cloud(conn, TAG_CLOUD_SIZE)?;
+    let max = cloud.iter().map(|tag| tag.posts).max().unwrap_or(1);
+    Ok(cloud
+        .into_iter()
+        .map(|tag| {
+            let size = match tag.posts * 4 / max {
+                0 => "text-sm",
+                1 => "text-base",
+                2 => "text-lg",
+                3 => "text-xl",
+                _ => "text-2xl",
             };
-            Ok(Html(error_template.render().unwrap()))
-        }
+            (tag, size)
+        })
+        .collect())
+}
+
+async fn tags_page(State(backend): State<Backend>) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let template = TagsTemplate {
+        cloud: tag_cloud(&mut conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Lists the published posts with a tag, redirecting to the normalized tag name
+async fn tag_page(
+    CsrfToken(csrf_token): CsrfToken,
+    State(backend): State<Backend>,
+    Path(name): Path<String>,
+) -> Result<Response, StatusCode> {
+    let normalized = validation::normalize_tag(&name).ok_or(StatusCode::NOT_FOUND)?;
+    if normalized != name {
+        return Ok(Redirect::permanent(&format!("/tags/{normalized}")).into_response());
     }
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Tag::find_by_name(&mut conn, &name)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let template = TagTemplate {
+        csrf_token,
+        feed_url: format!(
+            "/posts?{}",
+            serde_urlencoded::to_string([("tag", name.as_str())]).unwrap_or_default()
+        ),
+        cloud: tag_cloud(&mut conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        name,
+    };
+    Ok(Html(template.render().unwrap()).into_response())
 }
 
-/// Handles signout POST, ends the session
-async fn signout_post(Extension(mut session): Extension<AuthSession>) -> Redirect {
-    let _ = session.logout().await;
-    Redirect::to("/")
+/// Lists the user's drafts, scheduled posts and posts hidden by moderators
+async fn drafts_page(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let template = DraftsTemplate {
+        csrf_token,
+        drafts: Post::drafts_for_author(&mut conn, user.id)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Publishes one of the user's drafts now, removing it from the drafts page.
+///
+/// Posts hidden by a moderator can only be published again by a moderator.
+async fn post_publish(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user_id = auth_session.user.as_ref().map(|user| user.id);
+    let post = editable_post(&auth_session, &mut conn, post_id).await?;
+    let can_moderate = has_permission(&auth_session, Permission::MODERATE).await;
+    if post.hidden && !can_moderate {
+        let template = DraftRowTemplate {
+            post,
+            error: "This post was hidden by a moderator and cannot be published.".to_string(),
+        };
+        return Ok(Html(template.render().unwrap()));
+    }
+    if !post.hidden && post.author_id != user_id {
+        // Moderators may restore hidden posts but not publish someone else's draft
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match Post::publish(&mut conn, post.id) {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => Ok(Html(String::new())),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Moves one of the user's posts back to their drafts, removing it from the feed
+async fn post_unpublish(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post = owned_post(&auth_session, &mut conn, post_id).await?;
+    match Post::unpublish(&mut conn, post.id) {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => Ok(Html(String::new())),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduleForm {
+    pub publish_at: String,
+}
+
+/// Schedules one of the user's drafts, swapping its updated row in place
+async fn post_schedule(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+    Form(input): Form<ScheduleForm>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let post = owned_post(&auth_session, &mut conn, post_id).await?;
+    let publish_at = if post.hidden {
+        Err("This post was hidden by a moderator and cannot be published.")
+    } else {
+        parse_publish_at(&input.publish_at)
+    };
+    let publish_at = match publish_at {
+        Ok(publish_at) => publish_at,
+        Err(error) => {
+            let template = DraftRowTemplate {
+                post,
+                error: error.to_string(),
+            };
+            return Ok(Html(template.render().unwrap()));
+        }
+    };
+    let post = Post::schedule(&mut conn, post.id, publish_at)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let template = DraftRowTemplate {
+        post,
+        error: String::new(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Renders the signup form
+async fn signup_form(
+    CsrfToken(csrf_token): CsrfToken,
+    State(backend): State<Backend>,
+    Query(query): Query<SignupQuery>,
+) -> Html<String> {
+    let template = SignupFormTemplate {
+        csrf_token,
+        username: String::new(),
+        email: String::new(),
+        errors: SignupErrors::default(),
+        mode: backend.signup_mode,
+        invite: query.invite.unwrap_or_default(),
+    };
+    Html(template.render().unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignupQuery {
+    pub invite: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignupForm {
+    pub username: String,
+    pub password: String,
+    pub email: String,
+    /// Required in invite-only mode, ignored otherwise
+    #[serde(default)]
+    pub invite: String,
+}
+
+/// Failure of the signup transaction
+enum SignupError {
+    InvalidInvite,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for SignupError {
+    fn from(e: diesel::result::Error) -> Self {
+        SignupError::Database(e)
+    }
+}
+
+/// Handles signup POST, creates a new user with an email account.
+///
+/// Invalid fields re-render the whole form with per-field errors, swapped over the current
+/// card via `HX-Retarget`/`HX-Reselect`; success keeps the default target.
+#[axum::debug_handler(state = AppState)]
+async fn signup_post(
+    CsrfToken(csrf_token): CsrfToken,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    Form(input): Form<SignupForm>,
+) -> Result<Response, StatusCode> {
+    if backend.signup_mode.is_closed() {
+        let error_template = ErrorMessageTemplate {
+            message: "Signing up is closed.".to_string(),
+        };
+        return Ok(Html(error_template.render().unwrap()).into_response());
+    }
+
+    let username = input.username.trim().to_string();
+    let email = validation::normalize_email(&input.email);
+    let invite = input.invite.trim().to_string();
+    let mut errors = SignupErrors {
+        username: validation::validate_username(&username)
+            .err()
+            .map(str::to_string),
+        email: validation::validate_email(&email).err().map(str::to_string),
+        password: validation::validate_password(&input.password, &[&username, &email])
+            .err()
+            .map(str::to_string),
+        invite: (backend.signup_mode.requires_invite() && invite.is_empty())
+            .then(|| "An invite code is required to sign up.".to_string()),
+    };
+
+    if errors.is_empty() {
+        let mut conn = backend
+            .db
+            .get()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let hashed: String = generate_hash(&input.password);
+
+        // Redeeming the invite is part of the transaction, so a failed signup keeps the use.
+        let result = conn.transaction::<_, SignupError, _>(|conn| {
+            let invite_id = if backend.signup_mode.requires_invite() {
+                let invite = Invite::redeem(conn, &invite)?.ok_or(SignupError::InvalidInvite)?;
+                Some(invite.id)
+            } else {
+                None
+            };
+            let user = User::create(conn, username.clone())?;
+            let account = Account::create_email_account(conn, user.id, email.clone(), hashed)?;
+            Ok((account, invite_id))
+        });
+
+        match result {
+            Ok((account, invite_id)) => {
+                audit
+                    .record(audit::Entry {
+                        event: AuditEvent::SIGNUP,
+                        outcome: AuditEvent::SUCCESS,
+                        user_id: Some(account.user_id),
+                        subject: Some(&email),
+                        detail: invite_id.map(|id| format!("invite #{id}")).as_deref(),
+                    })
+                    .await;
+                if let Some(email) = account.email {
+                    send_verification_email(&mut conn, mailer.as_ref(), &email).await;
+                }
+                let success_template = SignupSuccessTemplate {};
+                return Ok(Html(success_template.render().unwrap()).into_response());
+            }
+            Err(SignupError::InvalidInvite) => {
+                errors.invite =
+                    Some("This invite code is invalid, expired or used up.".to_string());
+            }
+            Err(SignupError::Database(diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                info,
+            ))) => match info.constraint_name() {
+                Some(User::USERNAME_UNIQUE_INDEX) => {
+                    errors.username = Some("This username is already taken.".to_string());
+                }
+                Some(Account::EMAIL_UNIQUE_INDEX) => {
+                    errors.email =
+                        Some("An account already exists for this email address.".to_string());
+                    audit
+                        .record(audit::Entry {
+                            event: AuditEvent::SIGNUP,
+                            outcome: AuditEvent::FAILURE,
+                            subject: Some(&email),
+                            detail: Some("email already in use"),
+                            ..audit::Entry::default()
+                        })
+                        .await;
+                }
+                _ => {
+                    println!("Unexpected unique violation on signup: {}", info.message());
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            },
+            Err(SignupError::Database(e)) => {
+                println!("Failed to create user: {e}");
+                let error_template = ErrorMessageTemplate {
+                    message: "Failed to create your account. Please try again.".to_string(),
+                };
+                return Ok(Html(error_template.render().unwrap()).into_response());
+            }
+        }
+    }
+
+    let template = SignupFormTemplate {
+        csrf_token,
+        username,
+        email,
+        errors,
+        mode: backend.signup_mode,
+        invite,
+    };
+    Ok((
+        [
+            ("HX-Retarget", "#signup-card"),
+            ("HX-Reswap", "outerHTML"),
+            ("HX-Reselect", "#signup-card"),
+        ],
+        Html(template.render().unwrap()),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NextQuery {
+    pub next: Option<String>,
+}
+
+/// Renders the login form, carrying over a safe `next` path
+async fn login_form(
+    CsrfToken(csrf_token): CsrfToken,
+    State(oauth): State<Arc<OAuthProviders>>,
+    Query(query): Query<NextQuery>,
+) -> Html<String> {
+    let template = LoginFormTemplate {
+        csrf_token,
+        providers: oauth.list(),
+        next: query
+            .next
+            .filter(|next| redirect::safe_next(next).is_some()),
+    };
+    Html(template.render().unwrap())
+}
+
+/// Handles login POST, authenticates user and starts session
+#[axum::debug_handler(state = AppState)]
+async fn login_post(
+    Extension(mut auth_session): Extension<AuthSession>,
+    session: Session,
+    ClientIp(client_ip): ClientIp,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(throttle): State<LoginThrottle>,
+    Form(mut input): Form<Credentials>,
+) -> Result<Html<String>, StatusCode> {
+    input.email = validation::normalize_email(&input.email);
+    let client = client_ip.to_string();
+    match throttle.check(&input.email, &client).await {
+        Ok(Some(wait)) => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::LOGIN,
+                    outcome: AuditEvent::BLOCKED,
+                    subject: Some(&input.email),
+                    detail: Some("too many failed attempts"),
+                    ..audit::Entry::default()
+                })
+                .await;
+            let error_template = ErrorMessageTemplate {
+                message: format!(
+                    "Too many failed login attempts. Please try again in {}.",
+                    throttle::format_retry_after(wait)
+                ),
+            };
+            return Ok(Html(error_template.render().unwrap()));
+        }
+        Ok(None) => {}
+        Err(e) => println!("Failed to check login throttle: {e}"),
+    }
+
+    let result = backend.authenticate(input.clone()).await;
+    let throttle_result = match &result {
+        Ok(Some(_)) | Err(AuthError::EmailNotVerified) => {
+            throttle.record_success(&input.email).await
+        }
+        Ok(None) => throttle.record_failure(&input.email, &client).await,
+        Err(_) => Ok(()),
+    };
+    if let Err(e) = throttle_result {
+        println!("Failed to record login attempt: {e}");
+    }
+
+    match result {
+        Ok(Some(user)) => {
+            let redirect_url = complete_login(
+                &mut auth_session,
+                &session,
+                &audit,
+                &user,
+                "password",
+                input.next.as_deref(),
+            )
+            .await?;
+            let redirect_template = RedirectTemplate { redirect_url };
+            Ok(Html(redirect_template.render().unwrap()))
+        }
+        Err(AuthError::EmailNotVerified) => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::LOGIN,
+                    outcome: AuditEvent::FAILURE,
+                    subject: Some(&input.email),
+                    detail: Some("email not verified"),
+                    ..audit::Entry::default()
+                })
+                .await;
+            let template = EmailNotVerifiedTemplate { email: input.email };
+            Ok(Html(template.render().unwrap()))
+        }
+        _ => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::LOGIN,
+                    outcome: AuditEvent::FAILURE,
+                    subject: Some(&input.email),
+                    detail: Some("invalid email or password"),
+                    ..audit::Entry::default()
+                })
+                .await;
+            let error_template = ErrorMessageTemplate {
+                message: "Invalid email or password. Please try again.".to_string(),
+            };
+            Ok(Html(error_template.render().unwrap()))
+        }
+    }
+}
+
+/// Logs `user` in, or parks the login until the second factor has been provided.
+///
+/// Returns the URL the browser should go to next: the second factor form, or `next` when
+/// it is a safe same-origin path. `method` names the first factor in the audit log.
+async fn complete_login(
+    auth_session: &mut AuthSession,
+    session: &Session,
+    audit: &Audit,
+    user: &User,
+    method: &str,
+    next: Option<&str>,
+) -> Result<String, StatusCode> {
+    let next = redirect::next_or_default(next);
+    if user.totp_secret.is_some() {
+        session
+            .insert(
+                PendingLogin::SESSION_KEY,
+                PendingLogin::new(user.id, Some(next)),
+            )
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        audit
+            .record(audit::Entry {
+                event: AuditEvent::LOGIN,
+                outcome: AuditEvent::SUCCESS,
+                user_id: Some(user.id),
+                detail: Some(&format!("{method}, awaiting two-factor code")),
+                ..audit::Entry::default()
+            })
+            .await;
+        return Ok("/login/2fa".to_string());
+    }
+
+    auth_session
+        .login(user)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    mark_authenticated(session).await?;
+    audit
+        .record(audit::Entry {
+            event: AuditEvent::LOGIN,
+            outcome: AuditEvent::SUCCESS,
+            user_id: Some(user.id),
+            detail: Some(method),
+            ..audit::Entry::default()
+        })
+        .await;
+    Ok(next)
+}
+
+/// Session key holding the unix timestamp of the last full sign-in
+const AUTHENTICATED_AT_KEY: &str = "auth.authenticated_at";
+
+/// How recent a sign-in has to be to stand in for a password on sensitive actions
+const REAUTHENTICATION_WINDOW_MINUTES: i64 = 10;
+
+/// Records that the session has just gone through a full sign-in.
+async fn mark_authenticated(session: &Session) -> Result<(), StatusCode> {
+    session
+        .insert(AUTHENTICATED_AT_KEY, chrono::Utc::now().timestamp())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Whether the session signed in within the re-authentication window.
+async fn recently_authenticated(session: &Session) -> bool {
+    let window = chrono::Duration::minutes(REAUTHENTICATION_WINDOW_MINUTES).num_seconds();
+    matches!(
+        session.get::<i64>(AUTHENTICATED_AT_KEY).await,
+        Ok(Some(at)) if chrono::Utc::now().timestamp() - at <= window
+    )
+}
+
+/// How long a passwordless sign-in link stays valid
+const MAGIC_LINK_TTL_MINUTES: i64 = 15;
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkRequestForm {
+    pub email: String,
+    pub next: Option<String>,
+}
+
+/// Mails a single-use sign-in link to an email account
+async fn magic_link_request(
+    State(backend): State<Backend>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    Form(mut input): Form<MagicLinkRequestForm>,
+) -> Result<Html<String>, StatusCode> {
+    input.email = validation::normalize_email(&input.email);
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if Account::find_by_email(&mut conn, &input.email).is_ok() {
+        match VerificationToken::issue(
+            &mut conn,
+            VerificationToken::MAGIC_LINK,
+            &input.email,
+            chrono::Duration::minutes(MAGIC_LINK_TTL_MINUTES),
+        ) {
+            Ok(token) => {
+                let mut query = vec![("token", token)];
+                if let Some(next) = input.next.as_deref().and_then(redirect::safe_next) {
+                    query.push(("next", next.to_string()));
+                }
+                let link = format!(
+                    "{}/login/magic?{}",
+                    mailer::app_base_url(),
+                    serde_urlencoded::to_string(&query).unwrap_or_default()
+                );
+                let email = Email {
+                    to: input.email.clone(),
+                    subject: "Your sign-in link".to_string(),
+                    body: format!(
+                        "Someone asked for a link to sign in to your z3 app account.\n\nSign in by opening the link below:\n\n{link}\n\nThe link expires in {MAGIC_LINK_TTL_MINUTES} minutes and can only be used once. If you did not ask for this, you can ignore this email."
+                    ),
+                };
+                if let Err(e) = mailer.send(email).await {
+                    println!("Failed to send sign-in link: {e}");
+                }
+            }
+            Err(e) => println!("Failed to issue sign-in token: {e}"),
+        }
+    }
+
+    // Same answer whether or not the address is known, to avoid leaking accounts.
+    let template = InfoMessageTemplate {
+        message: "If an account exists for that address, a sign-in link is on its way.".to_string(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkQuery {
+    pub token: String,
+    pub next: Option<String>,
+}
+
+/// Renders the confirmation page of a mailed sign-in link.
+///
+/// The token is only consumed by the form submission, so that mail scanners prefetching
+/// links cannot use it up.
+async fn magic_link_form(
+    CsrfToken(csrf_token): CsrfToken,
+    State(backend): State<Backend>,
+    Query(query): Query<MagicLinkQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let valid = matches!(
+        VerificationToken::peek(&mut conn, VerificationToken::MAGIC_LINK, &query.token),
+        Ok(Some(_))
+    );
+
+    let template = MagicLinkTemplate {
+        csrf_token,
+        token: query.token,
+        next: query
+            .next
+            .filter(|next| redirect::safe_next(next).is_some()),
+        valid,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Consumes a sign-in link token and logs its owner in
+async fn magic_link_confirm(
+    Extension(mut auth_session): Extension<AuthSession>,
+    session: Session,
+    audit: Audit,
+    State(backend): State<Backend>,
+    Form(input): Form<MagicLinkQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let result = conn.transaction::<Option<i32>, diesel::result::Error, _>(|conn| {
+        let Some(email) =
+            VerificationToken::consume(conn, VerificationToken::MAGIC_LINK, &input.token)?
+        else {
+            return Ok(None);
+        };
+        // Opening the mailed link proves the address belongs to the user.
+        let account = Account::mark_email_verified(conn, &email)?;
+        Ok(Some(account.user_id))
+    });
+
+    let user = match result {
+        Ok(Some(user_id)) => backend
+            .get_user(&user_id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        Ok(None) => None,
+        Err(e) => {
+            println!("Failed to consume sign-in token: {e}");
+            None
+        }
+    };
+    let Some(user) = user else {
+        let error_template = ErrorMessageTemplate {
+            message: "This sign-in link is invalid, has expired or has already been used."
+                .to_string(),
+        };
+        return Ok(Html(error_template.render().unwrap()));
+    };
+
+    let redirect_url = complete_login(
+        &mut auth_session,
+        &session,
+        &audit,
+        &user,
+        "magic link",
+        input.next.as_deref(),
+    )
+    .await?;
+    let redirect_template = RedirectTemplate { redirect_url };
+    Ok(Html(redirect_template.render().unwrap()))
+}
+
+/// Renders the second login step for a login waiting on its TOTP code
+async fn two_factor_login_form(CsrfToken(csrf_token): CsrfToken, session: Session) -> Response {
+    match session.get::<PendingLogin>(PendingLogin::SESSION_KEY).await {
+        Ok(Some(pending)) if !pending.is_expired() => {
+            let template = TwoFactorLoginTemplate { csrf_token };
+            Html(template.render().unwrap()).into_response()
+        }
+        _ => Redirect::to("/login").into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeForm {
+    pub code: String,
+}
+
+/// Checks a TOTP code, or else consumes a recovery code, of a user with 2FA enabled.
+///
+/// A TOTP code that was already used counts as invalid.
+fn check_second_factor(
+    conn: &mut diesel::PgConnection,
+    user_id: i32,
+    secret: &str,
+    code: &str,
+) -> bool {
+    match two_factor::verify_code(secret, code) {
+        Some(step) => User::use_totp_step(conn, user_id, step).unwrap_or(false),
+        None => RecoveryCode::consume(conn, user_id, code).unwrap_or(false),
+    }
+}
+
+/// Checks the TOTP or recovery code of a pending login and completes it
+async fn two_factor_login_post(
+    Extension(mut auth_session): Extension<AuthSession>,
+    session: Session,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(throttle): State<LoginThrottle>,
+    Form(input): Form<TwoFactorCodeForm>,
+) -> Result<Html<String>, StatusCode> {
+    let error_page = |message: &str| {
+        let error_template = ErrorMessageTemplate {
+            message: message.to_string(),
+        };
+        Html(error_template.render().unwrap())
+    };
+
+    let pending: Option<PendingLogin> = session
+        .get(PendingLogin::SESSION_KEY)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(mut pending) = pending.filter(|p| !p.is_expired()) else {
+        let _ = session
+            .remove::<PendingLogin>(PendingLogin::SESSION_KEY)
+            .await;
+        return Ok(error_page("Your sign-in has expired. Please log in again."));
+    };
+
+    let Some(user) = backend
+        .get_user(&pending.user_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    else {
+        return Ok(error_page("Your sign-in has expired. Please log in again."));
+    };
+
+    // Counted per user rather than per pending login, so that redoing the password step
+    // does not buy more guesses.
+    match throttle.check_two_factor(user.id).await {
+        Ok(Some(wait)) => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::LOGIN_TWO_FACTOR,
+                    outcome: AuditEvent::BLOCKED,
+                    user_id: Some(user.id),
+                    detail: Some("too many failed attempts"),
+                    ..audit::Entry::default()
+                })
+                .await;
+            return Ok(error_page(&format!(
+                "Too many invalid codes. Please try again in {}.",
+                throttle::format_retry_after(wait)
+            )));
+        }
+        Ok(None) => {}
+        Err(e) => println!("Failed to check two-factor throttle: {e}"),
+    }
+
+    let valid = match &user.totp_secret {
+        Some(secret) => {
+            let mut conn = backend
+                .db
+                .get()
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            check_second_factor(&mut conn, user.id, secret, &input.code)
+        }
+        // 2FA was turned off in the meantime, the password step is enough.
+        None => true,
+    };
+    let throttle_result = if valid {
+        throttle.record_two_factor_success(user.id).await
+    } else {
+        throttle.record_two_factor_failure(user.id).await
+    };
+    if let Err(e) = throttle_result {
+        println!("Failed to update two-factor throttle: {e}");
+    }
+
+    if !valid {
+        audit
+            .record(audit::Entry {
+                event: AuditEvent::LOGIN_TWO_FACTOR,
+                outcome: AuditEvent::FAILURE,
+                user_id: Some(user.id),
+                ..audit::Entry::default()
+            })
+            .await;
+        pending.attempts += 1;
+        session
+            .insert(PendingLogin::SESSION_KEY, pending)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        return Ok(error_page("Invalid code. Please try again."));
+    }
+
+    let _ = session
+        .remove::<PendingLogin>(PendingLogin::SESSION_KEY)
+        .await;
+    auth_session
+        .login(&user)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    mark_authenticated(&session).await?;
+    audit.success(AuditEvent::LOGIN_TWO_FACTOR, user.id).await;
+    let redirect_template = RedirectTemplate {
+        redirect_url: redirect::next_or_default(pending.next.as_deref()),
+    };
+    Ok(Html(redirect_template.render().unwrap()))
+}
+
+/// Renders the two-factor settings page, starting an enrollment when 2FA is off
+async fn two_factor_settings(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    session: Session,
+    State(backend): State<Backend>,
+) -> Result<Response, StatusCode> {
+    let Some(user) = auth_session.user else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let template = if user.totp_secret.is_some() {
+        let mut conn = backend
+            .db
+            .get()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let has_password = Account::find_email_account_by_user(&mut conn, user.id)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .is_some_and(|account| account.password.is_some());
+        TwoFactorSettingsTemplate {
+            csrf_token,
+            enabled: true,
+            has_password,
+            remaining_codes: RecoveryCode::remaining(&mut conn, user.id).unwrap_or(0),
+            secret: String::new(),
+            otpauth_url: String::new(),
+            qr_svg: String::new(),
+        }
+    } else {
+        // Keep the same secret across reloads until the enrollment is confirmed.
+        let secret = match session.get::<String>(two_factor::PENDING_SECRET_KEY).await {
+            Ok(Some(secret)) => secret,
+            _ => {
+                let secret = two_factor::generate_secret();
+                session
+                    .insert(two_factor::PENDING_SECRET_KEY, &secret)
+                    .await
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                secret
+            }
+        };
+        let otpauth_url = two_factor::otpauth_url(&secret, &user.username);
+        TwoFactorSettingsTemplate {
+            csrf_token,
+            enabled: false,
+            has_password: false,
+            remaining_codes: 0,
+            qr_svg: two_factor::qr_svg(&otpauth_url),
+            otpauth_url,
+            secret,
+        }
+    };
+
+    Ok(Html(template.render().unwrap()).into_response())
+}
+
+/// Confirms a TOTP enrollment with a first code and hands out recovery codes
+async fn two_factor_enable(
+    Extension(mut auth_session): Extension<AuthSession>,
+    session: Session,
+    audit: Audit,
+    State(backend): State<Backend>,
+    Form(input): Form<TwoFactorCodeForm>,
+) -> Result<Html<String>, StatusCode> {
+    let Some(user) = auth_session.user.clone() else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+
+    let secret: Option<String> = session
+        .get(two_factor::PENDING_SECRET_KEY)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(secret) = secret else {
+        let error_template = ErrorMessageTemplate {
+            message: "Setup has expired. Please reload the page and try again.".to_string(),
+        };
+        return Ok(Html(error_template.render().unwrap()));
+    };
+    let Some(step) = two_factor::verify_code(&secret, &input.code) else {
+        let error_template = ErrorMessageTemplate {
+            message: "Invalid code. Please try again.".to_string(),
+        };
+        return Ok(Html(error_template.render().unwrap()));
+    };
+
+    let codes = two_factor::generate_recovery_codes();
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let updated = conn
+        .transaction::<User, diesel::result::Error, _>(|conn| {
+            User::enable_totp(conn, user.id, &secret, step)?;
+            RecoveryCode::replace_for_user(conn, user.id, &codes)?;
+            User::rotate_security_stamp(conn, user.id)
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let _ = session
+        .remove::<String>(two_factor::PENDING_SECRET_KEY)
+        .await;
+    // Other sessions are signed out by the new stamp; keep this one alive.
+    auth_session
+        .login(&updated)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit.success(AuditEvent::TWO_FACTOR_ENABLE, user.id).await;
+
+    let template = RecoveryCodesTemplate { codes };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorForm {
+    /// Missing for users without a password, who sign in through a provider
+    pub password: Option<String>,
+    pub code: String,
+}
+
+/// Turns two-factor authentication off after re-checking the current password and a
+/// current TOTP or recovery code.
+///
+/// Users signing in through a provider only have no password and confirm with the code
+/// alone. Wrong codes count towards the same limit as at login.
+async fn two_factor_disable(
+    Extension(mut auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(throttle): State<LoginThrottle>,
+    Form(input): Form<DisableTwoFactorForm>,
+) -> Result<Html<String>, StatusCode> {
+    let Some(user) = auth_session.user.clone() else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let Some(secret) = user.totp_secret.clone() else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let error_page = |message: &str| {
+        let error_template = ErrorMessageTemplate {
+            message: message.to_string(),
+        };
+        Html(error_template.render().unwrap())
+    };
+
+    match throttle.check_two_factor(user.id).await {
+        Ok(Some(wait)) => {
+            return Ok(error_page(&format!(
+                "Too many invalid codes. Please try again in {}.",
+                throttle::format_retry_after(wait)
+            )));
+        }
+        Ok(None) => {}
+        Err(e) => println!("Failed to check two-factor throttle: {e}"),
+    }
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let password_hash = Account::find_email_account_by_user(&mut conn, user.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .and_then(|account| account.password);
+    if let Some(hash) = password_hash {
+        let password_ok = input
+            .password
+            .is_some_and(|password| verify_password(&password, &hash).is_ok());
+        if !password_ok {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::TWO_FACTOR_DISABLE,
+                    outcome: AuditEvent::FAILURE,
+                    user_id: Some(user.id),
+                    detail: Some("incorrect password"),
+                    ..audit::Entry::default()
+                })
+                .await;
+            return Ok(error_page("Incorrect password."));
+        }
+    }
+
+    if !check_second_factor(&mut conn, user.id, &secret, &input.code) {
+        if let Err(e) = throttle.record_two_factor_failure(user.id).await {
+            println!("Failed to update two-factor throttle: {e}");
+        }
+        audit
+            .record(audit::Entry {
+                event: AuditEvent::TWO_FACTOR_DISABLE,
+                outcome: AuditEvent::FAILURE,
+                user_id: Some(user.id),
+                detail: Some("invalid code"),
+                ..audit::Entry::default()
+            })
+            .await;
+        return Ok(error_page("Invalid code. Please try again."));
+    }
+
+    let updated = conn
+        .transaction::<User, diesel::result::Error, _>(|conn| {
+            User::disable_totp(conn, user.id)?;
+            RecoveryCode::delete_for_user(conn, user.id)?;
+            User::rotate_security_stamp(conn, user.id)
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    auth_session
+        .login(&updated)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit.success(AuditEvent::TWO_FACTOR_DISABLE, user.id).await;
+
+    let redirect_template = RedirectTemplate {
+        redirect_url: "/settings/2fa".to_string(),
+    };
+    Ok(Html(redirect_template.render().unwrap()))
+}
+
+/// Handles signout POST, ends the session
+async fn signout_post(Extension(mut session): Extension<AuthSession>, audit: Audit) -> Redirect {
+    if let Ok(Some(user)) = session.logout().await {
+        audit.success(AuditEvent::SIGNOUT, user.id).await;
+    }
+    Redirect::to("/")
+}
+
+/// Parses an optional "expires in N days" field into an expiry time.
+///
+/// An empty field means no expiry; `Err` covers anything but a positive number of days
+/// that keeps the expiry within the supported date range.
+fn expiry_in_days(input: &str) -> Result<Option<chrono::NaiveDateTime>, ()> {
+    match input.trim() {
+        "" => Ok(None),
+        days => {
+            let days = days.parse::<i64>().map_err(|_| ())?;
+            if days <= 0 {
+                return Err(());
+            }
+            chrono::TimeDelta::try_days(days)
+                .and_then(|delta| chrono::Utc::now().naive_utc().checked_add_signed(delta))
+                .map(Some)
+                .ok_or(())
+        }
+    }
+}
+
+/// Lists the user's API tokens with a form to create new ones
+async fn api_tokens_page(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let template = ApiTokensTemplate {
+        csrf_token,
+        tokens: ApiToken::list_for_user(&mut conn, user.id)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        scopes: ApiToken::SCOPES.to_vec(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Creates an API token and shows it once.
+///
+/// Read as raw pairs since the form repeats the `scope` field once per checked scope.
+async fn api_token_create(
+    Extension(auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let error_page = |message: &str| {
+        let error_template = ErrorMessageTemplate {
+            message: message.to_string(),
+        };
+        Html(error_template.render().unwrap())
+    };
+
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.trim())
+            .unwrap_or_default()
+    };
+    let name = field("name");
+    if name.is_empty() {
+        return Ok(error_page("Please give the token a name."));
+    }
+    let scopes: Vec<String> = fields
+        .iter()
+        .filter(|(k, _)| k == "scope")
+        .map(|(_, v)| v.clone())
+        .collect();
+    if scopes.is_empty() {
+        return Ok(error_page("Please select at least one scope."));
+    }
+    if scopes
+        .iter()
+        .any(|scope| !ApiToken::SCOPES.iter().any(|(known, _)| known == scope))
+    {
+        return Ok(error_page("Unknown scope."));
+    }
+    let Ok(expires_at) = expiry_in_days(field("expires_in_days")) else {
+        return Ok(error_page("Invalid expiration."));
+    };
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (token, plain) = ApiToken::create(&mut conn, user.id, name, scopes, expires_at)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit
+        .record(audit::Entry {
+            event: AuditEvent::API_TOKEN_CREATE,
+            outcome: AuditEvent::SUCCESS,
+            user_id: Some(user.id),
+            subject: Some(&token.name),
+            detail: Some(&token.scopes.join(" ")),
+        })
+        .await;
+
+    let template = ApiTokenCreatedTemplate {
+        name: token.name,
+        token: plain,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Revokes one of the user's API tokens, removing its row
+async fn api_token_revoke(
+    Extension(auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    Path(token_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match ApiToken::revoke(&mut conn, user.id, token_id) {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => {
+            audit.success(AuditEvent::API_TOKEN_REVOKE, user.id).await;
+            Ok(Html(String::new()))
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// How many more invites the user may create: `None` for admins, who have no limit,
+/// otherwise what is left of `INVITE_QUOTA`
+async fn invites_left(
+    auth_session: &AuthSession,
+    backend: &Backend,
+    conn: &mut diesel::PgConnection,
+    user_id: i32,
+) -> Result<Option<i64>, StatusCode> {
+    if has_permission(auth_session, Permission::ADMIN).await {
+        return Ok(None);
+    }
+    let count =
+        Invite::count_for_inviter(conn, user_id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Some((backend.invite_quota - count).max(0)))
+}
+
+/// Lists the user's invites, or every invite for admins, with a form to create new ones
+async fn invites_page(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.clone().ok_or(StatusCode::UNAUTHORIZED)?;
+    let is_admin = has_permission(&auth_session, Permission::ADMIN).await;
+    if !is_admin && backend.invite_quota == 0 {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let template = InvitesTemplate {
+        csrf_token,
+        invites: Invite::list(&mut conn, (!is_admin).then_some(user.id))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        invites_left: invites_left(&auth_session, &backend, &mut conn, user.id).await?,
+        is_admin,
+        requires_invite: backend.signup_mode.requires_invite(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteForm {
+    #[serde(default)]
+    pub note: String,
+    /// Only honoured for admins, everyone else creates single-use invites
+    #[serde(default)]
+    pub max_uses: String,
+    #[serde(default)]
+    pub expires_in_days: String,
+}
+
+/// Creates an invite and shows its code and signup link once
+async fn invite_create(
+    Extension(auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    Form(input): Form<InviteForm>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.clone().ok_or(StatusCode::UNAUTHORIZED)?;
+    let error_page = |message: &str| {
+        let error_template = ErrorMessageTemplate {
+            message: message.to_string(),
+        };
+        Html(error_template.render().unwrap())
+    };
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let invites_left = invites_left(&auth_session, &backend, &mut conn, user.id).await?;
+    if invites_left == Some(0) {
+        return Ok(error_page("You have no invites left."));
+    }
+
+    let max_uses = match (invites_left, input.max_uses.trim()) {
+        (Some(_), _) | (None, "") => 1,
+        (None, uses) => match uses.parse::<i32>() {
+            Ok(uses) if uses > 0 => uses,
+            _ => return Ok(error_page("The number of uses must be a positive number.")),
+        },
+    };
+    let Ok(expires_at) = expiry_in_days(&input.expires_in_days) else {
+        return Ok(error_page("Invalid expiration."));
+    };
+
+    let (invite, code) =
+        Invite::create(&mut conn, user.id, input.note.trim(), max_uses, expires_at)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit
+        .record(audit::Entry {
+            event: AuditEvent::INVITE_CREATE,
+            outcome: AuditEvent::SUCCESS,
+            user_id: Some(user.id),
+            subject: Some(invite.note.as_str()).filter(|note| !note.is_empty()),
+            detail: Some(&format!("invite #{}", invite.id)),
+        })
+        .await;
+
+    let template = InviteCreatedTemplate {
+        link: format!("{}/signup?invite={code}", mailer::app_base_url()),
+        code,
+        max_uses: invite.max_uses,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Deletes an invite of the user, or any invite for admins, so that it can no longer be used
+async fn invite_revoke(
+    Extension(auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    Path(invite_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.clone().ok_or(StatusCode::UNAUTHORIZED)?;
+    let is_admin = has_permission(&auth_session, Permission::ADMIN).await;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match Invite::revoke(&mut conn, (!is_admin).then_some(user.id), invite_id) {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::INVITE_REVOKE,
+                    outcome: AuditEvent::SUCCESS,
+                    user_id: Some(user.id),
+                    detail: Some(&format!("invite #{invite_id}")),
+                    ..audit::Entry::default()
+                })
+                .await;
+            Ok(Html(String::new()))
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Header carrying the cursor of the next page of `/api/posts`
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// Lists a page of published posts as JSON for API clients with the `posts:read` scope.
+///
+/// The cursor of the next page, if any, is sent in the `X-Next-Cursor` header.
+async fn api_posts_list(
+    api_user: ApiUser,
+    State(backend): State<Backend>,
+    Query(query): Query<PostsQuery>,
+) -> Result<Response, Response> {
+    api_user
+        .require_scope(ApiToken::SCOPE_POSTS_READ)
+        .map_err(IntoResponse::into_response)?;
+    let cursor = query
+        .cursor()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "invalid cursor").into_response())?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    let page = Post::get_published(&mut conn, cursor, POSTS_PAGE_SIZE)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    let mut response = Json(page.posts).into_response();
+    if let Some(next) = page.next
+        && let Ok(value) = next.encode().parse()
+    {
+        response.headers_mut().insert(NEXT_CURSOR_HEADER, value);
+    }
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiPostInput {
+    pub title: String,
+    pub body: String,
+}
+
+/// Creates a post as the token's owner for API clients with the `posts:write` scope
+async fn api_posts_create(
+    api_user: ApiUser,
+    State(backend): State<Backend>,
+    Json(input): Json<ApiPostInput>,
+) -> Result<(StatusCode, Json<Post>), Response> {
+    api_user
+        .require_scope(ApiToken::SCOPE_POSTS_WRITE)
+        .map_err(IntoResponse::into_response)?;
+    if input.title.is_empty() || input.body.is_empty() {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    match Post::create(
+        &mut conn,
+        &input.title,
+        &input.body,
+        &Some(api_user.user.id),
+        chrono::Utc::now().naive_utc(),
+        true,
+        None,
+    ) {
+        Some(post) => Ok((StatusCode::CREATED, Json(post))),
+        None => Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+/// Renders the data export and account deletion page
+async fn account_settings(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    session: Session,
+    State(backend): State<Backend>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let has_password = Account::find_email_account_by_user(&mut conn, user.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .is_some_and(|account| account.password.is_some());
+
+    let template = AccountSettingsTemplate {
+        csrf_token,
+        has_password,
+        recently_authenticated: recently_authenticated(&session).await,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Downloads everything stored about the user as a JSON file
+async fn account_export(
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+) -> Result<Response, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let data = PersonalData::collect(&mut conn, user.id).map_err(|e| {
+        println!("Failed to export personal data: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let body =
+        serde_json::to_string_pretty(&data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let filename = format!("z3-app-data-{}.json", data.exported_at.format("%Y-%m-%d"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountForm {
+    pub password: Option<String>,
+}
+
+/// Deletes the user's account after re-authentication and signs them out everywhere
+async fn account_delete(
+    Extension(mut auth_session): Extension<AuthSession>,
+    session: Session,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(throttle): State<LoginThrottle>,
+    Form(input): Form<DeleteAccountForm>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.clone().ok_or(StatusCode::UNAUTHORIZED)?;
+    let error_page = |message: &str| {
+        let error_template = ErrorMessageTemplate {
+            message: message.to_string(),
+        };
+        Html(error_template.render().unwrap())
+    };
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let password_hash = Account::find_email_account_by_user(&mut conn, user.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .and_then(|account| account.password);
+    let reauthenticated = match password_hash {
+        Some(hash) => input
+            .password
+            .is_some_and(|password| verify_password(&password, &hash).is_ok()),
+        // Users without a password prove themselves by having signed in a moment ago.
+        None => recently_authenticated(&session).await,
+    };
+    if !reauthenticated {
+        audit
+            .record(audit::Entry {
+                event: AuditEvent::ACCOUNT_DELETE,
+                outcome: AuditEvent::FAILURE,
+                user_id: Some(user.id),
+                detail: Some("reauthentication failed"),
+                ..audit::Entry::default()
+            })
+            .await;
+        return Ok(error_page(
+            "Could not confirm it is you. Check your password or sign in again.",
+        ));
+    }
+
+    let is_admin = Role::for_user(&mut conn, user.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .is_some_and(|roles| roles.has_role(Role::ADMIN));
+    if is_admin
+        && Role::count_users(&mut conn, Role::ADMIN)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            <= 1
+    {
+        return Ok(error_page(
+            "You are the only admin. Make someone else an admin before deleting your account.",
+        ));
+    }
+
+    let emails = match PersonalData::delete(&mut conn, user.id) {
+        Ok(emails) => emails,
+        Err(e) => {
+            println!("Failed to delete account: {e}");
+            return Ok(error_page(
+                "Failed to delete your account. Please try again.",
+            ));
+        }
+    };
+    for email in emails {
+        if let Err(e) = throttle.forget_account(&email).await {
+            println!("Failed to forget login attempts of a deleted account: {e}");
+        }
+    }
+    // Stored sessions cascade with the user; any other session stops resolving to a user.
+    let _ = auth_session.logout().await;
+    // The user row is gone, so the event can only name them.
+    audit
+        .record(audit::Entry {
+            event: AuditEvent::ACCOUNT_DELETE,
+            outcome: AuditEvent::SUCCESS,
+            subject: Some(&user.username),
+            ..audit::Entry::default()
+        })
+        .await;
+
+    let redirect_template = RedirectTemplate {
+        redirect_url: "/".to_string(),
+    };
+    Ok(Html(redirect_template.render().unwrap()))
+}
+
+/// Lists the user's sessions with their device, address and activity
+async fn sessions_page(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    session: Session,
+    State(sessions): State<AppSessionStore>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let current_id = session.id().map(|id| id.to_string());
+
+    let stored = sessions
+        .list_for_user(user.id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tracked = stored.is_some();
+    let mut active: Vec<ActiveSession> = stored
+        .unwrap_or_default()
+        .iter()
+        .map(|row| ActiveSession::from_row(row, current_id.as_deref()))
+        .collect();
+    // The current session may not be stored yet, or the store may not list sessions at all.
+    if !active.iter().any(|s| s.current)
+        && let (Some(id), Ok(Some(meta))) = (
+            current_id.as_deref(),
+            session.get::<SessionMeta>(SessionMeta::SESSION_KEY).await,
+        )
+    {
+        active.insert(0, ActiveSession::from_meta(id, &meta));
+    }
+
+    let template = SessionsTemplate {
+        csrf_token,
+        sessions: active,
+        tracked,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Signs out one of the user's other sessions, removing its row
+async fn session_revoke(
+    Extension(auth_session): Extension<AuthSession>,
+    session: Session,
+    audit: Audit,
+    State(sessions): State<AppSessionStore>,
+    Path(handle): Path<String>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let current_id = session.id().map(|id| id.to_string());
+
+    let stored = sessions
+        .list_for_user(user.id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .unwrap_or_default();
+    let row = stored
+        .iter()
+        .find(|row| session_meta::session_handle(&row.id) == handle)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if current_id.as_deref() == Some(row.id.as_str()) {
+        // Signing out of this device goes through the regular sign out.
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let id = row
+        .id
+        .parse()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    sessions
+        .delete(&id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit.success(AuditEvent::SESSION_REVOKE, user.id).await;
+    Ok(Html(String::new()))
+}
+
+/// Signs out every session of the user except this one
+async fn sessions_revoke_others(
+    Extension(mut auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(sessions): State<AppSessionStore>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.clone().ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let updated = User::rotate_security_stamp(&mut conn, user.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if let Err(e) = sessions.delete_for_user(user.id).await {
+        println!("Failed to delete other sessions: {e}");
+    }
+    // Logging in again stores this session under a fresh id with the new stamp.
+    auth_session
+        .login(&updated)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit
+        .record(audit::Entry {
+            event: AuditEvent::SESSION_REVOKE,
+            outcome: AuditEvent::SUCCESS,
+            user_id: Some(user.id),
+            detail: Some("all other sessions"),
+            ..audit::Entry::default()
+        })
+        .await;
+
+    let redirect_template = RedirectTemplate {
+        redirect_url: "/settings/sessions".to_string(),
+    };
+    Ok(Html(redirect_template.render().unwrap()))
+}
+
+/// Signs the user out of every session on every device, including this one
+async fn signout_everywhere_post(
+    Extension(mut auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(sessions): State<AppSessionStore>,
+) -> Result<Redirect, StatusCode> {
+    let Some(user) = auth_session.user.clone() else {
+        return Ok(Redirect::to("/"));
+    };
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    User::rotate_security_stamp(&mut conn, user.id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // The rotated stamp already rejects old sessions, this just frees their rows.
+    if let Err(e) = sessions.delete_for_user(user.id).await {
+        println!("Failed to delete sessions on sign out everywhere: {e}");
+    }
+    let _ = auth_session.logout().await;
+    audit.success(AuditEvent::SIGNOUT_EVERYWHERE, user.id).await;
+    Ok(Redirect::to("/"))
+}
+
+/// Lists every user with toggles for their roles
+async fn admin_users(
+    CsrfToken(csrf_token): CsrfToken,
+    Extension(auth_session): Extension<AuthSession>,
+    State(backend): State<Backend>,
+) -> Result<Html<String>, StatusCode> {
+    let user = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let template = AdminUsersTemplate {
+        csrf_token,
+        users: Role::list_users(&mut conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        roles: Role::all(&mut conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        current_user_id: user.id,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Number of events per page of the audit log
+const AUDIT_PAGE_SIZE: i64 = 100;
+/// Most events a single JSON export holds
+const AUDIT_EXPORT_LIMIT: i64 = 10_000;
+
+/// Lists audit events, newest first, with filters and paging
+async fn admin_audit(
+    State(backend): State<Backend>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut entries = AuditEvent::search(&mut conn, &filter, AUDIT_PAGE_SIZE + 1)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let older_query = if entries.len() as i64 > AUDIT_PAGE_SIZE {
+        entries.truncate(AUDIT_PAGE_SIZE as usize);
+        let older = AuditFilter {
+            before: entries.last().map(|entry| entry.event.id),
+            ..filter.clone()
+        };
+        Some(serde_urlencoded::to_string(&older).unwrap_or_default())
+    } else {
+        None
+    };
+    let export_query = serde_urlencoded::to_string(AuditFilter {
+        before: None,
+        ..filter.clone()
+    })
+    .unwrap_or_default();
+
+    let template = AuditLogTemplate {
+        entries,
+        filter,
+        events: AuditEvent::EVENTS.to_vec(),
+        outcomes: AuditEvent::OUTCOMES.to_vec(),
+        export_query,
+        older_query,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Downloads the audit events matching the filters as JSON
+async fn admin_audit_export(
+    State(backend): State<Backend>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Response, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let entries = AuditEvent::search(&mut conn, &filter, AUDIT_EXPORT_LIMIT)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let body =
+        serde_json::to_string_pretty(&entries).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let filename = format!(
+        "z3-app-audit-{}.json",
+        chrono::Utc::now().format("%Y-%m-%d")
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Grants or revokes a role and re-renders the user's row
+async fn admin_toggle_role(
+    Extension(auth_session): Extension<AuthSession>,
+    audit: Audit,
+    State(backend): State<Backend>,
+    Path((user_id, role_name)): Path<(i32, String)>,
+) -> Result<Html<String>, StatusCode> {
+    let admin = auth_session.user.ok_or(StatusCode::UNAUTHORIZED)?;
+    // Admins cannot lock themselves out; another admin has to demote them.
+    if user_id == admin.id && role_name == Role::ADMIN {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let role = Role::find_by_name(&mut conn, &role_name)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let target = Role::for_user(&mut conn, user_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let revoking = target.has_role(&role.name);
+    let result = if revoking {
+        Role::revoke(&mut conn, user_id, role.id)
+    } else {
+        Role::assign(&mut conn, user_id, role.id)
+    };
+    result.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    audit
+        .record(audit::Entry {
+            event: AuditEvent::ROLE_CHANGE,
+            outcome: AuditEvent::SUCCESS,
+            user_id: Some(admin.id),
+            subject: Some(&target.username),
+            detail: Some(&format!(
+                "{} {}",
+                if revoking { "revoked" } else { "granted" },
+                role.name
+            )),
+        })
+        .await;
+
+    let template = AdminUserRowTemplate {
+        user: Role::for_user(&mut conn, user_id)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::NOT_FOUND)?,
+        roles: Role::all(&mut conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        current_user_id: admin.id,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// How many posts the moderation page lists
+const MODERATION_PAGE_SIZE: i64 = 50;
+
+/// Lists the most recent published posts with moderation actions
+async fn moderation_posts(
+    CsrfToken(csrf_token): CsrfToken,
+    State(backend): State<Backend>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let template = ModerationTemplate {
+        csrf_token,
+        posts: Post::recent_published(&mut conn, MODERATION_PAGE_SIZE)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Unpublishes a post, removing it from the moderation list
+async fn moderation_hide_post(
+    State(backend): State<Backend>,
+    Path(post_id): Path<i32>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match Post::hide(&mut conn, post_id) {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => Ok(Html(String::new())),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// How long an email verification link stays valid
+const VERIFICATION_TOKEN_TTL_HOURS: i64 = 24;
+
+/// Issues a verification token for `email` and mails the link to it.
+///
+/// Failures are logged rather than surfaced: the account exists either way and the user
+/// can ask for another link from the login form.
+async fn send_verification_email(
+    conn: &mut diesel::PgConnection,
+    mailer: &dyn Mailer,
+    email: &str,
+) {
+    let token = match VerificationToken::issue(
+        conn,
+        VerificationToken::VERIFY_EMAIL,
+        email,
+        chrono::Duration::hours(VERIFICATION_TOKEN_TTL_HOURS),
+    ) {
+        Ok(token) => token,
+        Err(e) => {
+            println!("Failed to issue verification token: {e}");
+            return;
+        }
+    };
+
+    let link = format!("{}/verify?token={token}", mailer::app_base_url());
+    let email = Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Welcome to z3 app!\n\nPlease confirm your email address by opening the link below:\n\n{link}\n\nThe link expires in {VERIFICATION_TOKEN_TTL_HOURS} hours."
+        ),
+    };
+    if let Err(e) = mailer.send(email).await {
+        println!("Failed to send verification email: {e}");
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    pub token: String,
+}
+
+/// Consumes an email verification token from a mailed link
+async fn verify_email(
+    State(backend): State<Backend>,
+    Query(query): Query<VerifyQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let verified = match VerificationToken::consume(
+        &mut conn,
+        VerificationToken::VERIFY_EMAIL,
+        &query.token,
+    ) {
+        Ok(Some(email)) => Account::mark_email_verified(&mut conn, &email).is_ok(),
+        Ok(None) => false,
+        Err(e) => {
+            println!("Failed to consume verification token: {e}");
+            false
+        }
+    };
+
+    let template = VerifyEmailTemplate { verified };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationForm {
+    pub email: String,
+}
+
+/// Sends a fresh verification link if the account is still unverified
+async fn verify_resend(
+    State(backend): State<Backend>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    Form(mut input): Form<ResendVerificationForm>,
+) -> Result<Html<String>, StatusCode> {
+    input.email = validation::normalize_email(&input.email);
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if let Ok(account) = Account::find_by_email(&mut conn, &input.email)
+        && account.email_verified.is_none()
+    {
+        send_verification_email(&mut conn, mailer.as_ref(), &input.email).await;
+    }
+
+    // Same answer whether or not the address is known, to avoid leaking accounts.
+    let template = InfoMessageTemplate {
+        message: "If that address needs verifying, a new link is on its way.".to_string(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// How long a password reset link stays valid
+const RESET_TOKEN_TTL_MINUTES: i64 = 30;
+
+/// Renders the "forgot password" form
+async fn forgot_password_form(CsrfToken(csrf_token): CsrfToken) -> Html<String> {
+    let template = ForgotPasswordTemplate { csrf_token };
+    Html(template.render().unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordForm {
+    pub email: String,
+}
+
+/// Mails a single-use password reset link to an email account
+async fn forgot_password_post(
+    State(backend): State<Backend>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    Form(mut input): Form<ForgotPasswordForm>,
+) -> Result<Html<String>, StatusCode> {
+    input.email = validation::normalize_email(&input.email);
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if Account::find_by_email(&mut conn, &input.email).is_ok() {
+        match VerificationToken::issue(
+            &mut conn,
+            VerificationToken::RESET_PASSWORD,
+            &input.email,
+            chrono::Duration::minutes(RESET_TOKEN_TTL_MINUTES),
+        ) {
+            Ok(token) => {
+                let link = format!("{}/reset-password?token={token}", mailer::app_base_url());
+                let email = Email {
+                    to: input.email.clone(),
+                    subject: "Reset your password".to_string(),
+                    body: format!(
+                        "Someone asked to reset the password of your z3 app account.\n\nChoose a new password by opening the link below:\n\n{link}\n\nThe link expires in {RESET_TOKEN_TTL_MINUTES} minutes and can only be used once. If you did not ask for this, you can ignore this email."
+                    ),
+                };
+                if let Err(e) = mailer.send(email).await {
+                    println!("Failed to send password reset email: {e}");
+                }
+            }
+            Err(e) => println!("Failed to issue password reset token: {e}"),
+        }
+    }
+
+    // Same answer whether or not the address is known, to avoid leaking accounts.
+    let template = InfoMessageTemplate {
+        message: "If an account exists for that address, a reset link is on its way.".to_string(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordQuery {
+    pub token: String,
+}
+
+/// Renders the "choose a new password" form for a mailed reset link
+async fn reset_password_form(
+    CsrfToken(csrf_token): CsrfToken,
+    State(backend): State<Backend>,
+    Query(query): Query<ResetPasswordQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let valid = matches!(
+        VerificationToken::peek(&mut conn, VerificationToken::RESET_PASSWORD, &query.token),
+        Ok(Some(_))
+    );
+
+    let template = ResetPasswordTemplate {
+        csrf_token,
+        token: query.token,
+        valid,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordForm {
+    pub token: String,
+    pub password: String,
+    pub password_confirmation: String,
+}
+
+/// Consumes a reset token, stores the new password and signs out every session
+async fn reset_password_post(
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(sessions): State<AppSessionStore>,
+    Form(input): Form<ResetPasswordForm>,
+) -> Result<Html<String>, StatusCode> {
+    let error_page = |message: &str| {
+        let error_template = ErrorMessageTemplate {
+            message: message.to_string(),
+        };
+        Html(error_template.render().unwrap())
+    };
+
+    if input.password != input.password_confirmation {
+        return Ok(error_page("Passwords do not match."));
+    }
+
+    let mut conn = backend
+        .db
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let email = VerificationToken::peek(&mut conn, VerificationToken::RESET_PASSWORD, &input.token)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    if let Err(message) = validation::validate_password(&input.password, &[&email]) {
+        return Ok(error_page(message));
+    }
+    let hashed = generate_hash(&input.password);
+
+    let result = conn.transaction::<Option<i32>, diesel::result::Error, _>(|conn| {
+        let Some(email) =
+            VerificationToken::consume(conn, VerificationToken::RESET_PASSWORD, &input.token)?
+        else {
+            return Ok(None);
+        };
+        let account = Account::find_by_email(conn, &email)?;
+        Account::update_password(conn, account.id, hashed)?;
+        // Following the mailed link proves the address belongs to the user
+        Account::mark_email_verified(conn, &email)?;
+        User::rotate_security_stamp(conn, account.user_id)?;
+        Ok(Some(account.user_id))
+    });
+
+    Ok(match result {
+        Ok(Some(user_id)) => {
+            // The rotated stamp already rejects old sessions, this just frees their rows.
+            if let Err(e) = sessions.delete_for_user(user_id).await {
+                println!("Failed to delete sessions after password reset: {e}");
+            }
+            audit.success(AuditEvent::PASSWORD_RESET, user_id).await;
+            let template = PasswordResetSuccessTemplate {};
+            Html(template.render().unwrap())
+        }
+        Ok(None) => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::PASSWORD_RESET,
+                    outcome: AuditEvent::FAILURE,
+                    detail: Some("invalid or expired link"),
+                    ..audit::Entry::default()
+                })
+                .await;
+            error_page("This reset link is invalid, has expired or has already been used.")
+        }
+        Err(e) => {
+            println!("Failed to reset password: {e}");
+            error_page("Failed to reset password. Please try again.")
+        }
+    })
+}
+
+/// Starts an OAuth authorization-code + PKCE flow by redirecting to the provider
+async fn oauth_start(
+    session: Session,
+    State(oauth): State<Arc<OAuthProviders>>,
+    Path(provider): Path<String>,
+    Query(query): Query<NextQuery>,
+) -> Result<Redirect, StatusCode> {
+    let provider = oauth.get(&provider).ok_or(StatusCode::NOT_FOUND)?;
+    let next = query
+        .next
+        .filter(|next| redirect::safe_next(next).is_some());
+    let (auth_url, pending) = provider.authorize_url(next);
+
+    session
+        .insert(PendingAuthorization::SESSION_KEY, pending)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Redirect::to(&auth_url))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallback {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Handles the provider redirect, exchanges the code and logs the user in
+async fn oauth_callback(
+    Extension(mut auth_session): Extension<AuthSession>,
+    session: Session,
+    audit: Audit,
+    State(backend): State<Backend>,
+    State(oauth): State<Arc<OAuthProviders>>,
+    Path(provider_name): Path<String>,
+    Query(params): Query<OAuthCallback>,
+) -> Result<Response, StatusCode> {
+    let provider = oauth.get(&provider_name).ok_or(StatusCode::NOT_FOUND)?;
+
+    // Always consume the pending state so a callback URL can only be used once.
+    let pending: Option<PendingAuthorization> = session
+        .remove(PendingAuthorization::SESSION_KEY)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let error_page = |message: &str| {
+        let error_template = ErrorMessageTemplate {
+            message: message.to_string(),
+        };
+        Html(error_template.render().unwrap()).into_response()
+    };
+
+    if let Some(error) = params.error {
+        println!("OAuth provider {provider_name} returned error: {error}");
+        return Ok(error_page(
+            "Sign-in was cancelled or denied by the provider.",
+        ));
+    }
+
+    let (Some(code), Some(state), Some(pending)) = (params.code, params.state, pending) else {
+        return Ok(error_page(
+            "Sign-in request is invalid or has expired. Please try again.",
+        ));
+    };
+    if pending.provider != provider_name || pending.csrf_state != state {
+        return Ok(error_page(
+            "Sign-in request is invalid or has expired. Please try again.",
+        ));
+    }
+
+    let identity = match provider
+        .exchange(&oauth.http, code, pending.pkce_verifier)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(e) => {
+            println!("OAuth exchange with {provider_name} failed: {e}");
+            return Ok(error_page("Could not complete sign-in with the provider."));
+        }
+    };
+
+    let link_to = auth_session.user.as_ref().map(|user| user.id);
+    match backend.authenticate_oauth(identity, link_to).await {
+        Ok(user) if link_to == Some(user.id) => {
+            let redirect_url = redirect::next_or_default(pending.next.as_deref());
+            Ok(Redirect::to(&redirect_url).into_response())
+        }
+        Ok(user) => {
+            let redirect_url = complete_login(
+                &mut auth_session,
+                &session,
+                &audit,
+                &user,
+                &format!("oauth {provider_name}"),
+                pending.next.as_deref(),
+            )
+            .await?;
+            Ok(Redirect::to(&redirect_url).into_response())
+        }
+        Err(AuthError::SignupClosed) => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::SIGNUP,
+                    outcome: AuditEvent::BLOCKED,
+                    detail: Some(&format!("oauth {provider_name}, signup not open")),
+                    ..audit::Entry::default()
+                })
+                .await;
+            Ok(error_page(
+                "No account is linked to this sign-in. Sign up with an invite first, then link the provider from your account.",
+            ))
+        }
+        Err(AuthError::UnverifiedAccountExists) => {
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::LOGIN,
+                    outcome: AuditEvent::BLOCKED,
+                    detail: Some(&format!("oauth {provider_name}, unverified email account")),
+                    ..audit::Entry::default()
+                })
+                .await;
+            Ok(error_page(
+                "An account with this email address exists but the address has not been verified. Sign in with its password, or reset the password from the login page, then link the provider from your account.",
+            ))
+        }
+        Err(e) => {
+            println!("Failed to sign in with {provider_name}: {e}");
+            audit
+                .record(audit::Entry {
+                    event: AuditEvent::LOGIN,
+                    outcome: AuditEvent::FAILURE,
+                    detail: Some(&format!("oauth {provider_name}")),
+                    ..audit::Entry::default()
+                })
+                .await;
+            Ok(error_page("Could not complete sign-in with the provider."))
+        }
+    }
 }
@@ -0,0 +1,121 @@
+//! Rendering of Markdown post bodies to HTML that is safe to embed in pages.
+//!
+//! Markdown allows raw HTML, so the output of the parser goes through an allow-list
+//! sanitizer: only formatting tags survive, links keep their `href` and `title` only and get
+//! `rel="nofollow ugc"`, and URLs are limited to web and mail schemes.
+use ammonia::Builder;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{Options, Parser, html};
+
+/// Tags kept in rendered bodies, everything else is stripped
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+static SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .tags(ALLOWED_TAGS.iter().copied().collect())
+        .tag_attributes(
+            [("a", ["href", "title"].into_iter().collect())]
+                .into_iter()
+                .collect(),
+        )
+        .url_schemes(["http", "https", "mailto"].into_iter().collect())
+        .clean_content_tags(["script", "style"].into_iter().collect())
+        .link_rel(Some("nofollow ugc"))
+        .strip_comments(true);
+    builder
+});
+
+/// Renders `markdown` to sanitized HTML
+pub fn render(markdown: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    let mut unsafe_html = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_formats_markdown() {
+        assert_eq!(
+            render("Some **bold** text"),
+            "<p>Some <strong>bold</strong> text</p>\n"
+        );
+        assert!(render("~~gone~~").contains("<del>gone</del>"));
+    }
+
+    #[test]
+    fn render_strips_scripts() {
+        let html = render("Hello <script>alert(1)</script> world");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("alert(1)"));
+
+        let html = render("<script>\nalert(1)\n</script>");
+        assert!(!html.contains("script"));
+        assert!(!html.contains("alert"));
+    }
+
+    #[test]
+    fn render_drops_javascript_links() {
+        let html = render("[click](javascript:alert(1))");
+        assert!(!html.contains("javascript:"));
+
+        let html = render("<a href=\"javascript:alert(1)\">click</a>");
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn render_marks_links_nofollow() {
+        let html = render("[site](https://example.com)");
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(html.contains("rel=\"nofollow ugc\""));
+    }
+
+    #[test]
+    fn render_removes_raw_html_outside_the_allow_list() {
+        let html =
+            render("<img src=x onerror=alert(1)> <iframe src=\"https://evil.com\"></iframe>");
+        assert!(!html.contains("<img"));
+        assert!(!html.contains("onerror"));
+        assert!(!html.contains("<iframe"));
+
+        let html = render("<p onclick=\"alert(1)\" style=\"color:red\">text</p>");
+        assert!(html.contains("text"));
+        assert!(!html.contains("onclick"));
+        assert!(!html.contains("style"));
+
+        // Inline code shows HTML as text rather than markup.
+        assert!(render("`<b>`").contains("&lt;b&gt;"));
+    }
+}
@@ -0,0 +1,432 @@
+//! OAuth2 / OpenID Connect sign-in.
+//!
+//! Providers are configured generically through environment variables so that any
+//! standards-compliant issuer (including a local mock OIDC server) can be plugged in:
+//!
+//! ```text
+//! OAUTH_PROVIDERS=mock
+//! OAUTH_REDIRECT_BASE_URL=http://localhost:3000
+//! OAUTH_MOCK_CLIENT_ID=z3-app
+//! OAUTH_MOCK_CLIENT_SECRET=secret
+//! OAUTH_MOCK_ISSUER=http://localhost:8080/default
+//! ```
+//!
+//! When only the issuer is given, the authorization, token and userinfo endpoints are
+//! read from `{issuer}/.well-known/openid-configuration`. Each endpoint can also be set
+//! explicitly with `OAUTH_<NAME>_AUTH_URL`, `OAUTH_<NAME>_TOKEN_URL` and
+//! `OAUTH_<NAME>_USERINFO_URL`.
+use oauth2::basic::{
+    BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+    BasicTokenType,
+};
+use oauth2::{
+    AuthUrl, AuthorizationCode, Client, ClientId, ClientSecret, CsrfToken, EndpointNotSet,
+    EndpointSet, ExtraTokenFields, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope,
+    StandardRevocableToken, StandardTokenResponse, TokenResponse, TokenUrl,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+
+/// Token response fields returned by OpenID Connect providers on top of plain OAuth2.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OidcTokenFields {
+    pub id_token: Option<String>,
+}
+
+impl ExtraTokenFields for OidcTokenFields {}
+
+type OidcTokenResponse = StandardTokenResponse<OidcTokenFields, BasicTokenType>;
+
+type OidcClient = Client<
+    BasicErrorResponse,
+    OidcTokenResponse,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointSet,
+>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    #[error("missing configuration value {0}")]
+    MissingConfig(String),
+    #[error("invalid endpoint url: {0}")]
+    InvalidUrl(#[from] oauth2::url::ParseError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("token exchange failed: {0}")]
+    TokenExchange(String),
+    #[error("userinfo response did not contain a subject")]
+    MissingSubject,
+}
+
+/// Subset of the OpenID Connect discovery document used to fill in missing endpoints.
+#[derive(Debug, Deserialize)]
+struct DiscoveryDocument {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+/// Standard OpenID Connect userinfo claims.
+#[derive(Debug, Deserialize)]
+struct UserInfo {
+    sub: Option<String>,
+    // Some non-OIDC providers use `id` instead of `sub`.
+    id: Option<serde_json::Value>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    preferred_username: Option<String>,
+    name: Option<String>,
+}
+
+/// Identity and tokens obtained from a completed authorization-code exchange.
+#[derive(Debug, Clone)]
+pub struct OAuthIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub id_token: Option<String>,
+    pub expires_at: Option<i32>,
+    pub token_type: String,
+    pub scope: Option<String>,
+}
+
+impl OAuthIdentity {
+    /// Picks a username for a freshly created user, falling back to the provider subject.
+    pub fn suggested_username(&self) -> String {
+        self.preferred_username
+            .clone()
+            .or_else(|| {
+                self.email
+                    .as_ref()
+                    .and_then(|email| email.split('@').next().map(str::to_string))
+            })
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| format!("{}-{}", self.provider, self.subject))
+    }
+}
+
+/// State kept in the session between the authorization redirect and the callback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingAuthorization {
+    pub provider: String,
+    pub csrf_state: String,
+    pub pkce_verifier: String,
+    /// Where to go once the sign-in completes
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+impl PendingAuthorization {
+    pub const SESSION_KEY: &'static str = "oauth.pending";
+}
+
+pub struct OAuthProvider {
+    pub name: String,
+    pub display_name: String,
+    client: OidcClient,
+    scopes: Vec<String>,
+    userinfo_url: String,
+}
+
+impl OAuthProvider {
+    /// Builds the provider authorization URL with a fresh CSRF state and PKCE challenge.
+    pub fn authorize_url(&self, next: Option<String>) -> (String, PendingAuthorization) {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, csrf_state) = self
+            .client
+            .authorize_url(CsrfToken::new_random)
+            .add_scopes(self.scopes.iter().cloned().map(Scope::new))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        let pending = PendingAuthorization {
+            provider: self.name.clone(),
+            csrf_state: csrf_state.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+            next,
+        };
+        (url.to_string(), pending)
+    }
+
+    /// Exchanges an authorization code for tokens and resolves the user's identity.
+    pub async fn exchange(
+        &self,
+        http: &reqwest::Client,
+        code: String,
+        pkce_verifier: String,
+    ) -> Result<OAuthIdentity, OAuthError> {
+        let token = self
+            .client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(http)
+            .await
+            .map_err(|e| OAuthError::TokenExchange(e.to_string()))?;
+
+        let access_token = token.access_token().secret().clone();
+        let info: UserInfo = http
+            .get(&self.userinfo_url)
+            .bearer_auth(&access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let subject = info
+            .sub
+            .or_else(|| {
+                info.id.map(|id| match id {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                })
+            })
+            .ok_or(OAuthError::MissingSubject)?;
+
+        let expires_at = token
+            .expires_in()
+            .map(|expires_in| (chrono::Utc::now() + expires_in).timestamp() as i32);
+        let scope = token.scopes().map(|scopes| {
+            scopes
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+
+        Ok(OAuthIdentity {
+            provider: self.name.clone(),
+            subject,
+            email: info.email,
+            email_verified: info.email_verified.unwrap_or(false),
+            preferred_username: info.preferred_username.or(info.name),
+            access_token,
+            refresh_token: token.refresh_token().map(|t| t.secret().clone()),
+            id_token: token.extra_fields().id_token.clone(),
+            expires_at,
+            token_type: token.token_type().as_ref().to_string(),
+            scope,
+        })
+    }
+}
+
+/// Registry of the OAuth providers enabled for this deployment.
+pub struct OAuthProviders {
+    providers: BTreeMap<String, OAuthProvider>,
+    pub http: reqwest::Client,
+}
+
+impl OAuthProviders {
+    /// Loads every provider listed in `OAUTH_PROVIDERS`.
+    ///
+    /// Providers that fail to configure are logged and skipped so that a broken provider
+    /// never prevents the email/password login from working.
+    pub async fn from_env() -> Self {
+        // Following redirects during the token exchange opens the client up to SSRF.
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build OAuth HTTP client");
+
+        let mut providers = BTreeMap::new();
+        let names = env::var("OAUTH_PROVIDERS").unwrap_or_default();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match Self::load_provider(&http, name).await {
+                Ok(provider) => {
+                    providers.insert(provider.name.clone(), provider);
+                }
+                Err(e) => eprintln!("Skipping OAuth provider {name}: {e}"),
+            }
+        }
+
+        Self { providers, http }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OAuthProvider> {
+        self.providers.get(name)
+    }
+
+    /// `(name, display name)` pairs for rendering sign-in buttons.
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.providers
+            .values()
+            .map(|p| (p.name.clone(), p.display_name.clone()))
+            .collect()
+    }
+
+    async fn load_provider(
+        http: &reqwest::Client,
+        name: &str,
+    ) -> Result<OAuthProvider, OAuthError> {
+        let prefix = format!("OAUTH_{}_", name.to_uppercase().replace('-', "_"));
+        let var = |key: &str| env::var(format!("{prefix}{key}")).ok();
+        let required =
+            |key: &str| var(key).ok_or_else(|| OAuthError::MissingConfig(format!("{prefix}{key}")));
+
+        let client_id = required("CLIENT_ID")?;
+        let client_secret = var("CLIENT_SECRET");
+
+        let discovery = match (var("AUTH_URL"), var("TOKEN_URL"), var("USERINFO_URL")) {
+            (Some(_), Some(_), Some(_)) => None,
+            _ => {
+                let issuer = required("ISSUER")?;
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                let doc: DiscoveryDocument = http
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Some(doc)
+            }
+        };
+
+        let auth_url = var("AUTH_URL")
+            .or_else(|| discovery.as_ref().map(|d| d.authorization_endpoint.clone()))
+            .ok_or_else(|| OAuthError::MissingConfig(format!("{prefix}AUTH_URL")))?;
+        let token_url = var("TOKEN_URL")
+            .or_else(|| discovery.as_ref().map(|d| d.token_endpoint.clone()))
+            .ok_or_else(|| OAuthError::MissingConfig(format!("{prefix}TOKEN_URL")))?;
+        let userinfo_url = var("USERINFO_URL")
+            .or_else(|| discovery.as_ref().and_then(|d| d.userinfo_endpoint.clone()))
+            .ok_or_else(|| OAuthError::MissingConfig(format!("{prefix}USERINFO_URL")))?;
+
+        let base_url = env::var("OAUTH_REDIRECT_BASE_URL")
+            .or_else(|_| env::var("APP_BASE_URL"))
+            .unwrap_or_else(|_| "http://localhost:3000".into());
+        let redirect_url = format!("{}/auth/{name}/callback", base_url.trim_end_matches('/'));
+
+        let mut client = Client::new(ClientId::new(client_id))
+            .set_auth_uri(AuthUrl::new(auth_url)?)
+            .set_token_uri(TokenUrl::new(token_url)?)
+            .set_redirect_uri(RedirectUrl::new(redirect_url)?);
+        if let Some(secret) = client_secret {
+            client = client.set_client_secret(ClientSecret::new(secret));
+        }
+
+        let scopes = var("SCOPES")
+            .unwrap_or_else(|| "openid email profile".to_string())
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+
+        Ok(OAuthProvider {
+            name: name.to_string(),
+            display_name: var("DISPLAY_NAME").unwrap_or_else(|| name.to_string()),
+            client,
+            scopes,
+            userinfo_url,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::accounts::Account;
+    use crate::db::models::invites::SignupMode;
+    use crate::db::models::users::{Backend, Error, User};
+    use crate::db::test_db;
+    use axum::{Json, Router, routing::get, routing::post};
+
+    /// Serves the token and userinfo endpoints of an OIDC provider vouching for `email`
+    async fn mock_oidc(subject: String, email: String) -> OAuthProvider {
+        let app = Router::new()
+            .route(
+                "/token",
+                post(|| async {
+                    Json(serde_json::json!({
+                        "access_token": "mock-access-token",
+                        "token_type": "Bearer",
+                        "expires_in": 3600,
+                    }))
+                }),
+            )
+            .route(
+                "/userinfo",
+                get(move || async move {
+                    Json(serde_json::json!({
+                        "sub": subject,
+                        "email": email,
+                        "email_verified": true,
+                    }))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = Client::new(ClientId::new("z3-app".to_string()))
+            .set_auth_uri(AuthUrl::new(format!("{base_url}/authorize")).unwrap())
+            .set_token_uri(TokenUrl::new(format!("{base_url}/token")).unwrap())
+            .set_redirect_uri(
+                RedirectUrl::new("http://localhost:3000/auth/mock/callback".to_string()).unwrap(),
+            );
+        OAuthProvider {
+            name: "mock".to_string(),
+            display_name: "Mock OIDC".to_string(),
+            client,
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            userinfo_url: format!("{base_url}/userinfo"),
+        }
+    }
+
+    #[tokio::test]
+    async fn verified_provider_email_links_only_verified_accounts() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let email = format!("{}@example.com", test_db::unique("victim-"));
+        let user = {
+            let mut conn = pool.get().unwrap();
+            // Registered by someone else before the owner of the address ever signed in
+            let user = User::create(&mut conn, test_db::unique("victim-")).unwrap();
+            Account::create_email_account(&mut conn, user.id, email.clone(), "hash".to_string())
+                .unwrap();
+            user
+        };
+        let backend = Backend {
+            db: std::sync::Arc::new(pool),
+            require_email_verification: false,
+            signup_mode: SignupMode::Open,
+            invite_quota: 0,
+        };
+        let provider = mock_oidc(test_db::unique("subject-"), email.clone()).await;
+        let identity = provider
+            .exchange(
+                &reqwest::Client::new(),
+                "code".to_string(),
+                "verifier".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(identity.email_verified);
+
+        let refused = backend.authenticate_oauth(identity.clone(), None).await;
+        assert!(matches!(refused, Err(Error::UnverifiedAccountExists)));
+        {
+            let mut conn = backend.db.get().unwrap();
+            let linked = Account::find_by_provider(&mut conn, "mock", &identity.subject).unwrap();
+            assert!(linked.is_none());
+            Account::mark_email_verified(&mut conn, &email).unwrap();
+        }
+
+        let linked = backend.authenticate_oauth(identity, None).await.unwrap();
+        assert_eq!(linked.id, user.id);
+    }
+}
@@ -0,0 +1,32 @@
+//! Background publishing of scheduled drafts.
+use crate::db::db_utils::PgPool;
+use crate::db::models::posts::Post;
+use std::sync::Arc;
+use tokio::task;
+
+/// Spawns a task publishing the drafts whose `publish_at` has passed, checking every `period`.
+///
+/// Posts go live up to `period` late; they are still dated at their scheduled time.
+pub fn spawn_scheduled_publishing(
+    db: Arc<PgPool>,
+    period: std::time::Duration,
+) -> task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let db = db.clone();
+            let result = task::spawn_blocking(move || {
+                let mut conn = db.get().map_err(|e| e.to_string())?;
+                Post::publish_due(&mut conn).map_err(|e| e.to_string())
+            })
+            .await;
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(count)) => println!("Published {count} scheduled posts"),
+                Ok(Err(e)) => eprintln!("Failed to publish scheduled posts: {e}"),
+                Err(e) => eprintln!("Failed to publish scheduled posts: {e}"),
+            }
+        }
+    })
+}
@@ -0,0 +1,75 @@
+//! Validation of the `next` URL users are sent to after logging in.
+//!
+//! Only same-origin relative paths are accepted, so that a crafted `/login?next=` link can
+//! never bounce a freshly logged-in user to another site.
+
+/// Where users go after logging in when no valid `next` was given.
+pub const DEFAULT_REDIRECT: &str = "/";
+
+/// Returns `next` if it is a safe same-origin path.
+///
+/// Rejects absolute URLs, scheme-relative URLs (`//evil.example`), backslashes (which
+/// browsers treat as slashes) and control characters.
+pub fn safe_next(next: &str) -> Option<&str> {
+    let is_path = next.starts_with('/') && !next.starts_with("//");
+    let has_unsafe_chars = next
+        .chars()
+        .any(|c| c == '\\' || c.is_control() || c.is_whitespace());
+    (is_path && !has_unsafe_chars).then_some(next)
+}
+
+/// Resolves an optional `next` value to the URL to redirect to.
+pub fn next_or_default(next: Option<&str>) -> String {
+    next.and_then(safe_next)
+        .unwrap_or(DEFAULT_REDIRECT)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_next_accepts_same_origin_paths() {
+        assert_eq!(safe_next("/"), Some("/"));
+        assert_eq!(safe_next("/posts?x=1"), Some("/posts?x=1"));
+        assert_eq!(safe_next("/posts/7#comments"), Some("/posts/7#comments"));
+    }
+
+    #[test]
+    fn safe_next_rejects_other_origins() {
+        for next in [
+            "//evil.com",
+            "//evil.com/path",
+            "/\\evil.com",
+            "\\\\evil.com",
+            "https://evil.com",
+            "javascript:alert(1)",
+            "evil.com",
+            "",
+        ] {
+            assert_eq!(safe_next(next), None, "{next:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn safe_next_rejects_control_characters() {
+        // Browsers drop tabs and newlines from URLs, turning these into `//evil.com`.
+        for next in [
+            "/\t/evil.com",
+            "/\n/evil.com",
+            "/\r/evil.com",
+            "/posts\0",
+            "/a b",
+        ] {
+            assert_eq!(safe_next(next), None, "{next:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn next_or_default_falls_back_to_the_home_page() {
+        assert_eq!(next_or_default(None), DEFAULT_REDIRECT);
+        assert_eq!(next_or_default(Some("//evil.com")), DEFAULT_REDIRECT);
+        assert_eq!(next_or_default(Some("/drafts")), "/drafts");
+    }
+}
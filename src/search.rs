@@ -0,0 +1,198 @@
+//! Translation of search box input to Postgres full-text queries, and highlighting of the
+//! matches in results.
+//!
+//! The input is a list of words, all of which must match. `"quoted words"` match as a
+//! phrase, `word*` matches every word starting with `word` and `-word` excludes posts
+//! containing it. Anything but letters and digits is dropped, so the input can never inject
+//! `tsquery` operators.
+
+/// Marks the start of a match in the text returned by `ts_headline`
+pub const MATCH_START: char = '\u{2}';
+/// Marks the end of a match in the text returned by `ts_headline`
+pub const MATCH_END: char = '\u{3}';
+
+/// Builds the `to_tsquery` text for `input`, `None` if it has nothing to look for
+pub fn to_tsquery(input: &str) -> Option<String> {
+    let mut terms = Vec::new();
+    let mut has_positive = false;
+    // Splitting on quotes alternates between free words and phrases; an unterminated
+    // quote runs to the end of the input.
+    for (index, segment) in input.split('"').enumerate() {
+        if index % 2 == 1 {
+            if let Some(phrase) = phrase(segment, false) {
+                terms.push(phrase);
+                has_positive = true;
+            }
+            continue;
+        }
+        for token in segment.split_whitespace() {
+            let (negated, token) = match token.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, token),
+            };
+            let (prefix, token) = match token.strip_suffix('*') {
+                Some(rest) => (true, rest),
+                None => (false, token),
+            };
+            let Some(term) = phrase(token, prefix) else {
+                continue;
+            };
+            if negated {
+                terms.push(format!("!{term}"));
+            } else {
+                terms.push(term);
+                has_positive = true;
+            }
+        }
+    }
+    // A query made only of exclusions would match nearly every post
+    has_positive.then(|| terms.join(" & "))
+}
+
+/// The words of `text` as a phrase, the last one matching as a prefix if `prefix` is set
+fn phrase(text: &str, prefix: bool) -> Option<String> {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let suffix = if prefix { ":*" } else { "" };
+    match words.as_slice() {
+        [] => None,
+        [word] => Some(format!("{word}{suffix}")),
+        words => Some(format!("({}{suffix})", words.join(" <-> "))),
+    }
+}
+
+/// Escapes a `ts_headline` result for HTML and turns the match markers into `<mark>` tags
+pub fn highlight(headline: &str) -> String {
+    let mut html = String::with_capacity(headline.len() + 32);
+    for c in headline.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#x27;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_db;
+    use diesel::RunQueryDsl;
+
+    /// Inputs trying to sneak `tsquery` syntax in
+    const HOSTILE_INPUTS: &[&str] = &[
+        "rust & !go",
+        "a | b",
+        "!rust",
+        "rust:*",
+        "title:A",
+        "(rust | go) & c",
+        "rust <-> go",
+        "rust <2> go",
+        "'rust'",
+        "\\rust",
+        "\"(a | b)\" -c:*",
+        "--rust**",
+        "-\"\" *",
+    ];
+
+    /// Whether `query` is only alphanumeric words joined by `&`, `<->` and parentheses,
+    /// each term possibly negated or ending in `:*`
+    fn is_plain_query(query: &str) -> bool {
+        query.split(" & ").all(|term| {
+            let term = term.strip_prefix('!').unwrap_or(term);
+            let term = term.strip_suffix(":*").unwrap_or(term);
+            let words = match term.strip_prefix('(') {
+                Some(inner) => match inner.strip_suffix(')') {
+                    Some(inner) => inner.split(" <-> ").collect(),
+                    None => return false,
+                },
+                None => vec![term],
+            };
+            words.iter().all(|word| {
+                let word = word.strip_suffix(":*").unwrap_or(word);
+                !word.is_empty() && word.chars().all(char::is_alphanumeric)
+            })
+        })
+    }
+
+    #[test]
+    fn to_tsquery_requires_every_word() {
+        assert_eq!(to_tsquery("rust  web"), Some("rust & web".to_string()));
+        assert_eq!(to_tsquery("Grüße 2024"), Some("Grüße & 2024".to_string()));
+    }
+
+    #[test]
+    fn to_tsquery_builds_phrases() {
+        assert_eq!(
+            to_tsquery("\"web framework\" rust"),
+            Some("(web <-> framework) & rust".to_string())
+        );
+        assert_eq!(to_tsquery("\"rust\""), Some("rust".to_string()));
+        // An unterminated quote runs to the end of the input
+        assert_eq!(
+            to_tsquery("rust \"web framework"),
+            Some("rust & (web <-> framework)".to_string())
+        );
+    }
+
+    #[test]
+    fn to_tsquery_matches_prefixes() {
+        assert_eq!(to_tsquery("frame*"), Some("frame:*".to_string()));
+        assert_eq!(
+            to_tsquery("web-frame*"),
+            Some("(web <-> frame:*)".to_string())
+        );
+        assert_eq!(to_tsquery("*"), None);
+    }
+
+    #[test]
+    fn to_tsquery_negates_words() {
+        assert_eq!(to_tsquery("rust -go"), Some("rust & !go".to_string()));
+        assert_eq!(to_tsquery("rust -go*"), Some("rust & !go:*".to_string()));
+        // Exclusions alone would match nearly everything
+        assert_eq!(to_tsquery("-go"), None);
+        assert_eq!(to_tsquery("-"), None);
+    }
+
+    #[test]
+    fn to_tsquery_drops_operators() {
+        assert_eq!(to_tsquery("rust & !go"), Some("rust & go".to_string()));
+        assert_eq!(to_tsquery("a | b"), Some("a & b".to_string()));
+        assert_eq!(to_tsquery("title:A"), Some("(title <-> A)".to_string()));
+        assert_eq!(to_tsquery("(rust"), Some("rust".to_string()));
+        assert_eq!(to_tsquery("& | ! : ( )"), None);
+        assert_eq!(to_tsquery(""), None);
+        for input in HOSTILE_INPUTS {
+            if let Some(query) = to_tsquery(input) {
+                assert!(is_plain_query(&query), "{input:?} gave {query:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn to_tsquery_output_is_accepted_by_postgres() {
+        let Some(pool) = test_db::pool() else {
+            return;
+        };
+        let conn = &mut pool.get().unwrap();
+        let inputs = ["rust web", "\"web framework\" frame* -go"];
+        for input in inputs.iter().chain(HOSTILE_INPUTS) {
+            let Some(query) = to_tsquery(input) else {
+                continue;
+            };
+            let parsed = diesel::sql_query("SELECT to_tsquery('english', $1)::text")
+                .bind::<diesel::sql_types::Text, _>(&query)
+                .execute(conn);
+            assert!(parsed.is_ok(), "{input:?} gave {query:?}: {parsed:?}");
+        }
+    }
+}
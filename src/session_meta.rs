@@ -0,0 +1,124 @@
+//! Metadata about logged-in sessions, listed on the active sessions page.
+//!
+//! The metadata lives in the session data itself, so it works with every session store;
+//! the database store also copies it into columns of the `sessions` table.
+use crate::client_info::{ClientIp, UserAgent, describe_user_agent};
+use crate::db::models::users::{AuthSession, Session as SessionRow};
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_login::tower_sessions::Session;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionMeta {
+    /// User the metadata was recorded for, a new login starts over
+    pub user_id: i32,
+    /// Unix timestamp of the sign-in
+    pub created_at: i64,
+    /// Unix timestamp of the last request, updated at most every `TOUCH_INTERVAL_SECONDS`
+    pub last_active_at: i64,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+impl SessionMeta {
+    pub const SESSION_KEY: &'static str = "session.meta";
+    /// Minimum delay between two activity updates, so that not every request saves the session
+    pub const TOUCH_INTERVAL_SECONDS: i64 = 60;
+}
+
+/// Middleware keeping the metadata of logged-in sessions up to date.
+///
+/// Anonymous sessions are left alone so that visitors do not each get a stored session.
+pub async fn track_session(
+    auth_session: AuthSession,
+    session: Session,
+    ClientIp(ip): ClientIp,
+    UserAgent(user_agent): UserAgent,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(user) = &auth_session.user {
+        let now = chrono::Utc::now().timestamp();
+        let ip = ip.to_string();
+        let current = match session.get::<SessionMeta>(SessionMeta::SESSION_KEY).await {
+            Ok(Some(meta)) if meta.user_id == user.id => Some(meta),
+            _ => None,
+        };
+        let updated = match current {
+            Some(meta)
+                if now - meta.last_active_at < SessionMeta::TOUCH_INTERVAL_SECONDS
+                    && meta.ip == ip
+                    && meta.user_agent == user_agent =>
+            {
+                None
+            }
+            Some(meta) => Some(SessionMeta {
+                last_active_at: now,
+                ip,
+                user_agent,
+                ..meta
+            }),
+            None => Some(SessionMeta {
+                user_id: user.id,
+                created_at: now,
+                last_active_at: now,
+                ip,
+                user_agent,
+            }),
+        };
+        if let Some(meta) = updated
+            && let Err(e) = session.insert(SessionMeta::SESSION_KEY, meta).await
+        {
+            eprintln!("Failed to record session metadata: {e}");
+        }
+    }
+    next.run(request).await
+}
+
+/// A session as shown on the active sessions page.
+#[derive(Debug, Clone)]
+pub struct ActiveSession {
+    /// Public reference to the session; the session id itself is the cookie secret
+    pub handle: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_active_at: chrono::NaiveDateTime,
+    pub ip: Option<String>,
+    pub device: String,
+    pub current: bool,
+}
+
+/// Public reference to a session id that can be put in pages and URLs.
+pub fn session_handle(session_id: &str) -> String {
+    crate::tokens::hash_token(session_id)[..16].to_string()
+}
+
+impl ActiveSession {
+    pub fn from_row(row: &SessionRow, current_id: Option<&str>) -> Self {
+        ActiveSession {
+            handle: session_handle(&row.id),
+            created_at: row.created_at,
+            last_active_at: row.last_active_at,
+            ip: row.ip_address.clone(),
+            device: describe_user_agent(row.user_agent.as_deref().unwrap_or_default()),
+            current: current_id == Some(row.id.as_str()),
+        }
+    }
+
+    pub fn from_meta(session_id: &str, meta: &SessionMeta) -> Self {
+        let timestamp = |ts: i64| {
+            chrono::DateTime::from_timestamp(ts, 0)
+                .unwrap_or_default()
+                .naive_utc()
+        };
+        ActiveSession {
+            handle: session_handle(session_id),
+            created_at: timestamp(meta.created_at),
+            last_active_at: timestamp(meta.last_active_at),
+            ip: Some(meta.ip.clone()),
+            device: describe_user_agent(&meta.user_agent),
+            current: true,
+        }
+    }
+}
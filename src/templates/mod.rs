@@ -3,22 +3,88 @@ use askama::Template;
 #[derive(Template)]
 #[template(path = "html/main.html")]
 pub struct MainTemplate {
-    pub posts: Vec<crate::db::models::posts::Post>,
+    pub csrf_token: String,
 }
 
 #[derive(Template)]
 #[template(path = "html/_components/post.html")]
 pub struct PostTemplate {
     pub post: crate::db::models::posts::Post,
+    /// Shows the edit and delete buttons, for the author and moderators
+    pub can_edit: bool,
+    /// Shows the unpublish button
+    pub is_author: bool,
+    /// Tag names, sorted
+    pub tags: Vec<String>,
+    pub reactions: crate::db::models::reactions::Reactions,
+}
+
+/// An unpublished post on the drafts page, with its publishing actions.
+#[derive(Template)]
+#[template(path = "html/_components/draft_row.html")]
+pub struct DraftRowTemplate {
+    pub post: crate::db::models::posts::Post,
+    /// Why the last action was refused, empty otherwise
+    pub error: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/drafts.html")]
+pub struct DraftsTemplate {
+    pub drafts: Vec<crate::db::models::posts::Post>,
+    pub csrf_token: String,
+}
+
+/// Put at the top of the feed in place of a post saved as a draft.
+#[derive(Template)]
+#[template(path = "html/_components/post_draft_saved.html")]
+pub struct PostDraftSavedTemplate {
+    pub title: String,
+    pub publish_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Last item of a feed page, replaced by the next page once scrolled into view.
+#[derive(Template)]
+#[template(path = "html/_components/posts_more.html")]
+pub struct PostsMoreTemplate {
+    /// Feed URL of the next page
+    pub url: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/post_edit.html")]
+pub struct PostEditTemplate {
+    pub post_id: i32,
+    pub title: String,
+    pub body: String,
+    /// Comma separated tags
+    pub tags: String,
+    /// Why the last submission was refused, empty on first display
+    pub error: String,
 }
 
 #[derive(Template)]
 #[template(path = "html/_components/login_form.html")]
-pub struct LoginFormTemplate {}
+pub struct LoginFormTemplate {
+    /// `(name, display name)` of the configured OAuth providers
+    pub providers: Vec<(String, String)>,
+    /// Validated path to return to after logging in
+    pub next: Option<String>,
+    pub csrf_token: String,
+}
 
 #[derive(Template)]
 #[template(path = "html/_components/signup_form.html")]
-pub struct SignupFormTemplate {}
+pub struct SignupFormTemplate {
+    /// Values to refill after a failed attempt; the password never is
+    pub username: String,
+    pub email: String,
+    pub errors: crate::validation::SignupErrors,
+    pub mode: crate::db::models::invites::SignupMode,
+    /// Invite code, prefilled from the `?invite=` link
+    pub invite: String,
+    pub csrf_token: String,
+}
 
 #[derive(Template)]
 #[template(path = "html/_components/welcome.html")]
@@ -28,6 +94,11 @@ pub struct WelcomeTemplate {}
 #[template(path = "html/_components/user_header.html")]
 pub struct UserHeaderTemplate {
     pub username: String,
+    pub can_admin: bool,
+    pub can_moderate: bool,
+    /// Signup is invite-only and the user may hand out invites
+    pub can_invite: bool,
+    pub csrf_token: String,
 }
 
 #[derive(Template)]
@@ -45,3 +116,274 @@ pub struct ErrorMessageTemplate {
 pub struct RedirectTemplate {
     pub redirect_url: String,
 }
+
+#[derive(Template)]
+#[template(path = "html/_components/info_message.html")]
+pub struct InfoMessageTemplate {
+    pub message: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/email_not_verified.html")]
+pub struct EmailNotVerifiedTemplate {
+    pub email: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/verify_email.html")]
+pub struct VerifyEmailTemplate {
+    pub verified: bool,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/forgot_password.html")]
+pub struct ForgotPasswordTemplate {
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/reset_password.html")]
+pub struct ResetPasswordTemplate {
+    pub token: String,
+    pub valid: bool,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/password_reset_success.html")]
+pub struct PasswordResetSuccessTemplate {}
+
+#[derive(Template)]
+#[template(path = "html/_components/two_factor_settings.html")]
+pub struct TwoFactorSettingsTemplate {
+    pub enabled: bool,
+    /// Whether turning 2FA off asks for the password, see `two_factor_disable`
+    pub has_password: bool,
+    pub remaining_codes: i64,
+    pub secret: String,
+    pub otpauth_url: String,
+    /// Inline SVG markup, rendered unescaped
+    pub qr_svg: String,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/recovery_codes.html")]
+pub struct RecoveryCodesTemplate {
+    pub codes: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/two_factor_login.html")]
+pub struct TwoFactorLoginTemplate {
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/admin_users.html")]
+pub struct AdminUsersTemplate {
+    pub users: Vec<crate::db::models::roles::UserRoles>,
+    pub roles: Vec<crate::db::models::roles::Role>,
+    /// The admin viewing the page, who cannot take their own admin role away
+    pub current_user_id: i32,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/admin_user_row.html")]
+pub struct AdminUserRowTemplate {
+    pub user: crate::db::models::roles::UserRoles,
+    pub roles: Vec<crate::db::models::roles::Role>,
+    pub current_user_id: i32,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/moderation.html")]
+pub struct ModerationTemplate {
+    pub posts: Vec<crate::db::models::posts::Post>,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/api_tokens.html")]
+pub struct ApiTokensTemplate {
+    pub tokens: Vec<crate::db::models::api_tokens::ApiToken>,
+    /// `(scope, description)` of the scopes offered in the creation form
+    pub scopes: Vec<(&'static str, &'static str)>,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/api_token_created.html")]
+pub struct ApiTokenCreatedTemplate {
+    pub name: String,
+    /// Plain token, shown only this once
+    pub token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/magic_link.html")]
+pub struct MagicLinkTemplate {
+    pub token: String,
+    pub next: Option<String>,
+    pub valid: bool,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/account_settings.html")]
+pub struct AccountSettingsTemplate {
+    /// Whether deletion is confirmed with the password
+    pub has_password: bool,
+    /// Whether a password-less user signed in recently enough to delete their account
+    pub recently_authenticated: bool,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/sessions.html")]
+pub struct SessionsTemplate {
+    pub sessions: Vec<crate::session_meta::ActiveSession>,
+    /// Whether sessions on other devices can be listed
+    pub tracked: bool,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/csrf_error.html")]
+pub struct CsrfErrorTemplate {}
+
+#[derive(Template)]
+#[template(path = "html/_components/audit_log.html")]
+pub struct AuditLogTemplate {
+    pub entries: Vec<crate::db::models::audit_events::AuditEntry>,
+    pub filter: crate::db::models::audit_events::AuditFilter,
+    pub events: Vec<&'static str>,
+    pub outcomes: Vec<&'static str>,
+    /// Query string of the current filters, for the export link
+    pub export_query: String,
+    /// Query string of the next page, `None` on the last one
+    pub older_query: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/invites.html")]
+pub struct InvitesTemplate {
+    pub invites: Vec<crate::db::models::invites::InviteListing>,
+    /// Invites the user may still create, `None` when unlimited
+    pub invites_left: Option<i64>,
+    /// Admins see everyone's invites and choose how many times an invite can be used
+    pub is_admin: bool,
+    /// Whether signing up currently needs an invite
+    pub requires_invite: bool,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/invite_created.html")]
+pub struct InviteCreatedTemplate {
+    /// Plain code, shown only this once
+    pub code: String,
+    /// Signup page with the code filled in
+    pub link: String,
+    pub max_uses: i32,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/search.html")]
+pub struct SearchTemplate {
+    pub q: String,
+    pub hits: Vec<crate::db::models::posts::SearchHit>,
+    /// Next page of results, loaded when scrolled into view
+    pub more_url: Option<String>,
+    pub first_page: bool,
+}
+
+/// Results swapped into the search page as the query is typed, or appended to them.
+#[derive(Template)]
+#[template(path = "html/_components/search_results.html")]
+pub struct SearchResultsTemplate {
+    pub q: String,
+    pub hits: Vec<crate::db::models::posts::SearchHit>,
+    pub more_url: Option<String>,
+    pub first_page: bool,
+}
+
+/// Every tag in use, sized by how many posts carry it.
+#[derive(Template)]
+#[template(path = "html/_components/tags.html")]
+pub struct TagsTemplate {
+    /// Tags with their text size class
+    pub cloud: Vec<(crate::db::models::tags::TagCount, &'static str)>,
+}
+
+/// Published posts carrying a tag, loaded page by page like the home feed.
+#[derive(Template)]
+#[template(path = "html/_components/tag.html")]
+pub struct TagTemplate {
+    pub csrf_token: String,
+    pub name: String,
+    /// First page of the tag's feed
+    pub feed_url: String,
+    pub cloud: Vec<(crate::db::models::tags::TagCount, &'static str)>,
+}
+
+/// A post with its comments, at `/posts/{id}`.
+#[derive(Template)]
+#[template(path = "html/_components/post_page.html")]
+pub struct PostPageTemplate {
+    pub csrf_token: String,
+    pub title: String,
+    /// The post as rendered in the feed
+    pub post_html: String,
+    /// Rendered [`CommentTemplate`]s of the top-level comments
+    pub comments_html: String,
+    /// Rendered [`CommentFormTemplate`] for new top-level comments
+    pub comment_form_html: String,
+}
+
+/// A comment followed by its replies.
+#[derive(Template)]
+#[template(path = "html/_components/comment.html")]
+pub struct CommentTemplate {
+    pub listing: crate::db::models::comments::CommentListing,
+    /// Shows the edit and delete buttons, for the author and moderators
+    pub can_edit: bool,
+    /// Shows the reply button, unless the thread is at its depth limit
+    pub can_reply: bool,
+    /// Rendered `CommentTemplate`s of the replies
+    pub replies: String,
+}
+
+/// The content of a comment without its replies, swapped back in after an edit.
+#[derive(Template)]
+#[template(path = "html/_components/comment_body.html")]
+pub struct CommentBodyTemplate {
+    pub listing: crate::db::models::comments::CommentListing,
+    pub can_edit: bool,
+    pub can_reply: bool,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/comment_form.html")]
+pub struct CommentFormTemplate {
+    pub post_id: i32,
+    /// Comment replied to, `None` for a top-level comment
+    pub parent_id: Option<i32>,
+}
+
+#[derive(Template)]
+#[template(path = "html/_components/comment_edit.html")]
+pub struct CommentEditTemplate {
+    pub comment_id: i32,
+    pub body: String,
+    /// Why the last submission was refused, empty on first display
+    pub error: String,
+}
+
+/// The reaction buttons of a post, swapped in when one is toggled.
+#[derive(Template)]
+#[template(path = "html/_components/post_reactions.html")]
+pub struct PostReactionsTemplate {
+    pub reactions: crate::db::models::reactions::Reactions,
+}
@@ -0,0 +1,465 @@
+//! Failed login tracking with exponential backoff and temporary lockout.
+//!
+//! Failures are counted both per account and per client address. Once a key goes over its
+//! free attempts it is locked for `base_delay * 2^(extra failures)`, capped at `max_delay`.
+//! Counters are forgotten after `window` without failures.
+//!
+//! Counters live in Postgres (`login_attempts`) so that every replica sees the same state;
+//! [`MemoryAttemptStore`] keeps them in process for tests and single-node development.
+use crate::db::models::login_attempts::LoginAttempt;
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::task;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ThrottleError {
+    #[error(transparent)]
+    Diesel(#[from] diesel::result::Error),
+    #[error(transparent)]
+    Pool(#[from] r2d2::Error),
+    #[error(transparent)]
+    TaskJoin(#[from] tokio::task::JoinError),
+}
+
+/// How many failures a key tolerates and how hard it is locked afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottlePolicy {
+    pub free_attempts: i32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub window: Duration,
+}
+
+impl ThrottlePolicy {
+    /// Per-account policy: a handful of typos, then lock quickly.
+    pub fn account() -> Self {
+        Self {
+            free_attempts: 5,
+            base_delay: Duration::seconds(30),
+            max_delay: Duration::hours(1),
+            window: Duration::hours(24),
+        }
+    }
+
+    /// Per-address policy: more lenient, since several users may share an address.
+    pub fn client() -> Self {
+        Self {
+            free_attempts: 20,
+            base_delay: Duration::seconds(30),
+            max_delay: Duration::hours(1),
+            window: Duration::hours(1),
+        }
+    }
+
+    /// Per-account policy for two-factor codes. A code is one of a million, so the lock
+    /// comes sooner and lasts longer than for passwords; the counter survives signing in
+    /// with the password again.
+    pub fn two_factor() -> Self {
+        Self {
+            free_attempts: 5,
+            base_delay: Duration::minutes(1),
+            max_delay: Duration::hours(1),
+            window: Duration::hours(24),
+        }
+    }
+
+    /// Computes the counter after one more failure at `now`.
+    pub fn apply_failure(
+        &self,
+        key: &str,
+        previous: Option<LoginAttempt>,
+        now: NaiveDateTime,
+    ) -> LoginAttempt {
+        let failures = match previous {
+            Some(prev) if now - prev.last_failure_at < self.window => prev.failures + 1,
+            _ => 1,
+        };
+        let locked_until = (failures > self.free_attempts).then(|| {
+            let exponent = (failures - self.free_attempts - 1).min(20) as u32;
+            let delay = self
+                .base_delay
+                .checked_mul(2i32.saturating_pow(exponent))
+                .unwrap_or(self.max_delay)
+                .min(self.max_delay);
+            now + delay
+        });
+
+        LoginAttempt {
+            key: key.to_string(),
+            failures,
+            last_failure_at: now,
+            locked_until,
+        }
+    }
+}
+
+/// Storage for failed login counters.
+#[async_trait]
+pub trait AttemptStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<LoginAttempt>, ThrottleError>;
+
+    /// Records one failure for `key` atomically and returns the new counter.
+    async fn record_failure(
+        &self,
+        key: &str,
+        policy: ThrottlePolicy,
+    ) -> Result<LoginAttempt, ThrottleError>;
+
+    async fn reset(&self, key: &str) -> Result<(), ThrottleError>;
+
+    /// Forgets counters whose last failure is older than `older_than`.
+    async fn purge_stale(&self, older_than: Duration) -> Result<(), ThrottleError>;
+}
+
+/// Counters stored in the `login_attempts` table.
+pub struct PgAttemptStore {
+    db: Arc<Pool<ConnectionManager<PgConnection>>>,
+}
+
+impl PgAttemptStore {
+    pub fn new(db: Arc<Pool<ConnectionManager<PgConnection>>>) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl AttemptStore for PgAttemptStore {
+    async fn get(&self, key: &str) -> Result<Option<LoginAttempt>, ThrottleError> {
+        let db = self.db.clone();
+        let key = key.to_string();
+        task::spawn_blocking(move || {
+            let mut conn = db.get()?;
+            Ok(LoginAttempt::find(&mut conn, &key)?)
+        })
+        .await?
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        policy: ThrottlePolicy,
+    ) -> Result<LoginAttempt, ThrottleError> {
+        let db = self.db.clone();
+        let key = key.to_string();
+        task::spawn_blocking(move || {
+            let mut conn = db.get()?;
+            let now = Utc::now().naive_utc();
+            Ok(LoginAttempt::update_with(&mut conn, &key, |previous| {
+                policy.apply_failure(&key, previous, now)
+            })?)
+        })
+        .await?
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), ThrottleError> {
+        let db = self.db.clone();
+        let key = key.to_string();
+        task::spawn_blocking(move || {
+            let mut conn = db.get()?;
+            LoginAttempt::delete(&mut conn, &key)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn purge_stale(&self, older_than: Duration) -> Result<(), ThrottleError> {
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            let mut conn = db.get()?;
+            LoginAttempt::delete_stale(&mut conn, Utc::now().naive_utc() - older_than)?;
+            Ok(())
+        })
+        .await?
+    }
+}
+
+/// In-process counters, for tests and single-node development.
+#[derive(Default)]
+pub struct MemoryAttemptStore {
+    attempts: Mutex<HashMap<String, LoginAttempt>>,
+}
+
+#[async_trait]
+impl AttemptStore for MemoryAttemptStore {
+    async fn get(&self, key: &str) -> Result<Option<LoginAttempt>, ThrottleError> {
+        Ok(self.attempts.lock().unwrap().get(key).cloned())
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        policy: ThrottlePolicy,
+    ) -> Result<LoginAttempt, ThrottleError> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let next = policy.apply_failure(key, attempts.remove(key), Utc::now().naive_utc());
+        attempts.insert(key.to_string(), next.clone());
+        Ok(next)
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), ThrottleError> {
+        self.attempts.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn purge_stale(&self, older_than: Duration) -> Result<(), ThrottleError> {
+        let now = Utc::now().naive_utc();
+        self.attempts.lock().unwrap().retain(|_, attempt| {
+            attempt.last_failure_at >= now - older_than
+                || attempt.locked_until.is_some_and(|until| until > now)
+        });
+        Ok(())
+    }
+}
+
+/// Login throttle checking both the account and the client address of an attempt.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    store: Arc<dyn AttemptStore>,
+    account_policy: ThrottlePolicy,
+    client_policy: ThrottlePolicy,
+    two_factor_policy: ThrottlePolicy,
+}
+
+impl LoginThrottle {
+    pub fn new(store: Arc<dyn AttemptStore>) -> Self {
+        Self {
+            store,
+            account_policy: ThrottlePolicy::account(),
+            client_policy: ThrottlePolicy::client(),
+            two_factor_policy: ThrottlePolicy::two_factor(),
+        }
+    }
+
+    /// `LOGIN_THROTTLE_STORE=memory` keeps counters in process, otherwise they go to Postgres.
+    pub fn from_env(db: Arc<Pool<ConnectionManager<PgConnection>>>) -> Self {
+        match std::env::var("LOGIN_THROTTLE_STORE").as_deref() {
+            Ok("memory") => Self::new(Arc::new(MemoryAttemptStore::default())),
+            _ => Self::new(Arc::new(PgAttemptStore::new(db))),
+        }
+    }
+
+    fn account_key(email: &str) -> String {
+        format!("account:{}", email.trim().to_lowercase())
+    }
+
+    fn client_key(client: &str) -> String {
+        format!("ip:{client}")
+    }
+
+    fn two_factor_key(user_id: i32) -> String {
+        format!("2fa:user:{user_id}")
+    }
+
+    /// Returns how long to wait when either the account or the client is locked.
+    pub async fn check(
+        &self,
+        email: &str,
+        client: &str,
+    ) -> Result<Option<std::time::Duration>, ThrottleError> {
+        let mut wait = None;
+        for key in [Self::account_key(email), Self::client_key(client)] {
+            wait = wait.max(self.locked_for(&key).await?);
+        }
+        Ok(wait)
+    }
+
+    async fn locked_for(&self, key: &str) -> Result<Option<std::time::Duration>, ThrottleError> {
+        let now = Utc::now().naive_utc();
+        Ok(self
+            .store
+            .get(key)
+            .await?
+            .and_then(|a| a.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| (until - now).to_std().unwrap_or_default()))
+    }
+
+    pub async fn record_failure(&self, email: &str, client: &str) -> Result<(), ThrottleError> {
+        self.store
+            .record_failure(&Self::account_key(email), self.account_policy)
+            .await?;
+        self.store
+            .record_failure(&Self::client_key(client), self.client_policy)
+            .await?;
+        Ok(())
+    }
+
+    /// Clears the account counter. The client counter is left alone so that one valid
+    /// account cannot be used to reset it between guesses against others.
+    pub async fn record_success(&self, email: &str) -> Result<(), ThrottleError> {
+        self.forget_account(email).await
+    }
+
+    /// Drops the counter of an account, e.g. once it has been deleted.
+    pub async fn forget_account(&self, email: &str) -> Result<(), ThrottleError> {
+        self.store.reset(&Self::account_key(email)).await
+    }
+
+    /// Returns how long to wait when the user's two-factor codes are locked.
+    pub async fn check_two_factor(
+        &self,
+        user_id: i32,
+    ) -> Result<Option<std::time::Duration>, ThrottleError> {
+        self.locked_for(&Self::two_factor_key(user_id)).await
+    }
+
+    pub async fn record_two_factor_failure(&self, user_id: i32) -> Result<(), ThrottleError> {
+        self.store
+            .record_failure(&Self::two_factor_key(user_id), self.two_factor_policy)
+            .await?;
+        Ok(())
+    }
+
+    /// Clears the two-factor counter. Only a valid code does this, not a valid password.
+    pub async fn record_two_factor_success(&self, user_id: i32) -> Result<(), ThrottleError> {
+        self.store.reset(&Self::two_factor_key(user_id)).await
+    }
+
+    /// Spawns a task forgetting stale counters every `period`.
+    pub fn spawn_cleanup(&self, period: std::time::Duration) -> task::JoinHandle<()> {
+        let store = self.store.clone();
+        let older_than = self
+            .account_policy
+            .window
+            .max(self.client_policy.window)
+            .max(self.two_factor_policy.window);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = store.purge_stale(older_than).await {
+                    eprintln!("Failed to purge login attempts: {e}");
+                }
+            }
+        })
+    }
+}
+
+/// Formats a wait such as "2 minutes" for error messages.
+pub fn format_retry_after(wait: std::time::Duration) -> String {
+    let seconds = wait.as_secs().max(1);
+    let (amount, unit) = if seconds < 60 {
+        (seconds, "second")
+    } else if seconds < 3600 {
+        (seconds.div_ceil(60), "minute")
+    } else {
+        (seconds.div_ceil(3600), "hour")
+    };
+    if amount == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{amount} {unit}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> NaiveDateTime {
+        chrono::DateTime::from_timestamp(1_800_000_000 + seconds, 0)
+            .unwrap()
+            .naive_utc()
+    }
+
+    #[test]
+    fn apply_failure_locks_with_exponential_backoff() {
+        let policy = ThrottlePolicy::account();
+        let mut attempt = None;
+        for _ in 0..policy.free_attempts {
+            let next = policy.apply_failure("key", attempt, at(0));
+            assert_eq!(next.locked_until, None);
+            attempt = Some(next);
+        }
+
+        let first_lock = policy.apply_failure("key", attempt, at(0));
+        assert_eq!(first_lock.failures, policy.free_attempts + 1);
+        assert_eq!(first_lock.locked_until, Some(at(0) + policy.base_delay));
+        let second_lock = policy.apply_failure("key", Some(first_lock), at(0));
+        assert_eq!(
+            second_lock.locked_until,
+            Some(at(0) + policy.base_delay * 2)
+        );
+
+        let capped = LoginAttempt {
+            failures: 1_000,
+            ..second_lock
+        };
+        let next = policy.apply_failure("key", Some(capped), at(0));
+        assert_eq!(next.locked_until, Some(at(0) + policy.max_delay));
+    }
+
+    #[test]
+    fn apply_failure_restarts_after_the_window() {
+        let policy = ThrottlePolicy::client();
+        let previous = LoginAttempt {
+            key: "key".to_string(),
+            failures: 50,
+            last_failure_at: at(0),
+            locked_until: Some(at(60)),
+        };
+        let later = at(policy.window.num_seconds());
+        let next = policy.apply_failure("key", Some(previous), later);
+        assert_eq!(next.failures, 1);
+        assert_eq!(next.locked_until, None);
+    }
+
+    #[test]
+    fn format_retry_after_rounds_up_to_the_unit() {
+        let format = |seconds| format_retry_after(std::time::Duration::from_secs(seconds));
+        assert_eq!(format(0), "1 second");
+        assert_eq!(format(45), "45 seconds");
+        assert_eq!(format(60), "1 minute");
+        assert_eq!(format(61), "2 minutes");
+        assert_eq!(format(3600), "1 hour");
+        assert_eq!(format(3 * 3600 + 1), "4 hours");
+    }
+
+    #[tokio::test]
+    async fn memory_store_locks_and_resets_an_account() {
+        let throttle = LoginThrottle::new(Arc::new(MemoryAttemptStore::default()));
+        let free_attempts = ThrottlePolicy::account().free_attempts;
+        for _ in 0..free_attempts {
+            throttle
+                .record_failure("someone@example.com", "192.0.2.1")
+                .await
+                .unwrap();
+        }
+        let check = |email| throttle.check(email, "192.0.2.2");
+        assert_eq!(check("someone@example.com").await.unwrap(), None);
+
+        throttle
+            .record_failure("Someone@Example.com ", "192.0.2.1")
+            .await
+            .unwrap();
+        assert!(check("someone@example.com").await.unwrap().is_some());
+        assert_eq!(check("other@example.com").await.unwrap(), None);
+
+        throttle
+            .record_success("someone@example.com")
+            .await
+            .unwrap();
+        assert_eq!(check("someone@example.com").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn two_factor_failures_survive_a_password_success() {
+        let throttle = LoginThrottle::new(Arc::new(MemoryAttemptStore::default()));
+        for _ in 0..=ThrottlePolicy::two_factor().free_attempts {
+            throttle.record_two_factor_failure(7).await.unwrap();
+        }
+        throttle
+            .record_success("someone@example.com")
+            .await
+            .unwrap();
+        assert!(throttle.check_two_factor(7).await.unwrap().is_some());
+        assert_eq!(throttle.check_two_factor(8).await.unwrap(), None);
+
+        throttle.record_two_factor_success(7).await.unwrap();
+        assert_eq!(throttle.check_two_factor(7).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,19 @@
+//! Helpers for the random secrets we hand out by email or link (verification, reset, ...).
+//!
+//! Only the SHA-256 digest of a token is ever stored, so a leaked database dump cannot be
+//! replayed against the verification endpoints.
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Generates a URL-safe token carrying 256 bits of randomness.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hex encoded SHA-256 digest of `token`, as stored in the database.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
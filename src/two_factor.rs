@@ -0,0 +1,142 @@
+//! TOTP (RFC 6238) second factor and its one-time recovery codes.
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Issuer shown by authenticator apps next to the account name.
+pub const ISSUER: &str = "z3 app";
+
+/// How many recovery codes are handed out when 2FA is enabled.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Session key holding the secret of an enrollment that has not been confirmed yet.
+pub const PENDING_SECRET_KEY: &str = "totp.pending_secret";
+
+/// A login that passed the password check and is waiting for the second factor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub user_id: i32,
+    /// Unix timestamp after which the password step has to be done again
+    pub expires_at: i64,
+    pub attempts: u8,
+    /// Where to go once the second factor has been checked
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+impl PendingLogin {
+    pub const SESSION_KEY: &'static str = "totp.pending_login";
+    /// Seconds the user has to enter a code after a successful password check
+    pub const TTL_SECONDS: i64 = 300;
+    /// Wrong codes accepted before the password step has to be done again
+    pub const MAX_ATTEMPTS: u8 = 5;
+
+    pub fn new(user_id: i32, next: Option<String>) -> Self {
+        Self {
+            user_id,
+            expires_at: chrono::Utc::now().timestamp() + Self::TTL_SECONDS,
+            attempts: 0,
+            next,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        chrono::Utc::now().timestamp() > self.expires_at || self.attempts >= Self::MAX_ATTEMPTS
+    }
+}
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+/// Seconds covered by one TOTP code
+const STEP_SECONDS: i64 = 30;
+
+fn totp(secret: &str, account_name: &str) -> Option<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        // Drift is handled by `verify_code`, which needs to know the matching step.
+        0,
+        STEP_SECONDS as u64,
+        bytes,
+        Some(ISSUER.to_string()),
+        // ':' separates issuer and account in otpauth labels.
+        account_name.replace(':', "_"),
+    )
+    .ok()
+}
+
+/// Checks a 6-digit code against `secret`, tolerating one step of clock drift.
+///
+/// Returns the time step the code belongs to, so that callers can refuse a step that
+/// has already been used.
+pub fn verify_code(secret: &str, code: &str) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let totp = totp(secret, "")?;
+    let current = chrono::Utc::now().timestamp() / STEP_SECONDS;
+    (current - 1..=current + 1).find(|step| totp.check(&code, (step * STEP_SECONDS) as u64))
+}
+
+/// `otpauth://` URI understood by authenticator apps.
+pub fn otpauth_url(secret: &str, account_name: &str) -> String {
+    totp(secret, account_name)
+        .map(|totp| totp.get_url())
+        .unwrap_or_default()
+}
+
+/// Renders `data` as an inline SVG QR code.
+pub fn qr_svg(data: &str) -> String {
+    qrcode::QrCode::new(data.as_bytes())
+        .map(|code| {
+            code.render::<qrcode::render::svg::Color>()
+                .min_dimensions(200, 200)
+                .build()
+        })
+        .unwrap_or_default()
+}
+
+/// Generates fresh human-friendly recovery codes such as `K7QF2-M9XDP`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    // No 0/O or 1/I to avoid transcription mistakes.
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// Canonical form of a recovery code as typed by the user, used before hashing.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_code_returns_the_step_of_the_code() {
+        let secret = generate_secret();
+        let totp = totp(&secret, "").unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let step = now / STEP_SECONDS;
+
+        let code = totp.generate(now as u64);
+        assert_eq!(verify_code(&secret, &code), Some(step));
+        let previous = totp.generate(((step - 1) * STEP_SECONDS) as u64);
+        assert_eq!(verify_code(&secret, &previous), Some(step - 1));
+        let stale = totp.generate(((step - 3) * STEP_SECONDS) as u64);
+        assert_ne!(verify_code(&secret, &stale), Some(step - 3));
+    }
+}
@@ -0,0 +1,280 @@
+//! Input rules for usernames, email addresses, passwords, post tags and comments.
+
+/// Usernames are 3 to 32 ASCII letters, digits, `_` or `-`, starting with a letter or digit.
+pub const USERNAME_MIN_LEN: usize = 3;
+pub const USERNAME_MAX_LEN: usize = 32;
+/// Passwords are hashed with a slow hash, the upper bound keeps hashing cheap enough.
+pub const PASSWORD_MIN_LEN: usize = 10;
+pub const PASSWORD_MAX_LEN: usize = 128;
+/// Longest address allowed by RFC 5321.
+pub const EMAIL_MAX_LEN: usize = 254;
+/// Tags are up to 32 lowercase letters, digits or `-`, and a post has at most 5 of them.
+pub const TAG_MAX_LEN: usize = 32;
+pub const MAX_TAGS_PER_POST: usize = 5;
+/// Comments are up to 2000 characters, and replies nest at most 5 levels deep.
+pub const COMMENT_MAX_LEN: usize = 2000;
+pub const COMMENT_MAX_DEPTH: i32 = 5;
+
+/// Per-field validation errors of the signup form, `None` for valid fields.
+#[derive(Debug, Clone, Default)]
+pub struct SignupErrors {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub invite: Option<String>,
+}
+
+impl SignupErrors {
+    pub fn is_empty(&self) -> bool {
+        self.username.is_none()
+            && self.email.is_none()
+            && self.password.is_none()
+            && self.invite.is_none()
+    }
+}
+
+/// Canonical form of an email address, used for storage and every lookup.
+///
+/// The whole address is lowercased: the local part is case-sensitive in theory, but no
+/// mainstream provider treats it that way and users do not expect it.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Checks the shape of an already normalized email address.
+pub fn validate_email(email: &str) -> Result<(), &'static str> {
+    if email.is_empty() {
+        return Err("Please enter your email address.");
+    }
+    if email.len() > EMAIL_MAX_LEN {
+        return Err("This email address is too long.");
+    }
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err("Please enter a valid email address.")
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<(), &'static str> {
+    let len = username.chars().count();
+    if len < USERNAME_MIN_LEN {
+        return Err("Usernames must be at least 3 characters long.");
+    }
+    if len > USERNAME_MAX_LEN {
+        return Err("Usernames must be at most 32 characters long.");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err("Usernames may only contain letters, digits, '_' and '-'.");
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err("Usernames must start with a letter or a digit.");
+    }
+    Ok(())
+}
+
+/// Checks password strength.
+///
+/// `personal` holds values the password must not contain, such as the username or the
+/// email address.
+pub fn validate_password(password: &str, personal: &[&str]) -> Result<(), &'static str> {
+    let len = password.chars().count();
+    if len < PASSWORD_MIN_LEN {
+        return Err("Passwords must be at least 10 characters long.");
+    }
+    if len > PASSWORD_MAX_LEN {
+        return Err("Passwords must be at most 128 characters long.");
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|present| **present).count() < 2 {
+        return Err("Passwords must mix at least two of lowercase, uppercase, digits and symbols.");
+    }
+
+    let lowered = password.to_lowercase();
+    let contains_personal = personal
+        .iter()
+        .map(|value| value.split('@').next().unwrap_or_default().to_lowercase())
+        .any(|value| value.len() >= USERNAME_MIN_LEN && lowered.contains(&value));
+    if contains_personal {
+        return Err("Passwords must not contain your username or email address.");
+    }
+    Ok(())
+}
+
+/// Turns a suggestion (e.g. an OAuth display name) into something `validate_username` accepts.
+pub fn sanitize_username(suggestion: &str) -> String {
+    let mut username: String = suggestion
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || c == '.' {
+                '_'
+            } else {
+                c
+            }
+        })
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(USERNAME_MAX_LEN - 4)
+        .collect();
+    if username.len() < USERNAME_MIN_LEN {
+        username = format!("user{username}");
+    }
+    username
+}
+
+/// Canonical form of a tag, used for storage and lookups.
+///
+/// Lowercased without a leading `#`, with spaces, `_` and other punctuation turned into
+/// single dashes, so that "Web Dev", "#web_dev" and "web-dev" are the same tag. `None`
+/// when nothing is left.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let mut normalized = String::new();
+    for c in tag.trim().trim_start_matches('#').chars() {
+        if c.is_alphanumeric() {
+            normalized.extend(c.to_lowercase());
+        } else if !normalized.is_empty() && !normalized.ends_with('-') {
+            normalized.push('-');
+        }
+    }
+    let normalized = normalized.trim_end_matches('-');
+    (!normalized.is_empty()).then(|| normalized.to_string())
+}
+
+/// Parses the comma separated tags of the post form into distinct normalized tags, in the
+/// order they were typed.
+pub fn parse_tags(input: &str) -> Result<Vec<String>, &'static str> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in input.split(',').filter_map(normalize_tag) {
+        if tag.chars().count() > TAG_MAX_LEN {
+            return Err("Tags must be at most 32 characters long.");
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > MAX_TAGS_PER_POST {
+        return Err("Posts can have at most 5 tags.");
+    }
+    Ok(tags)
+}
+
+/// Checks the body of a comment, returning it trimmed.
+pub fn validate_comment(body: &str) -> Result<&str, &'static str> {
+    let body = body.trim();
+    if body.is_empty() {
+        return Err("Comments cannot be empty.");
+    }
+    if body.chars().count() > COMMENT_MAX_LEN {
+        return Err("Comments must be at most 2000 characters long.");
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_email_checks_the_shape_of_the_address() {
+        assert!(validate_email("someone@example.com").is_ok());
+        assert!(validate_email("first.last+tag@mail.example.org").is_ok());
+
+        for invalid in [
+            "someone",
+            "@example.com",
+            "someone@",
+            "someone@localhost",
+            "someone@.example.com",
+            "someone@example.com.",
+            "some@one@example.com",
+            "some one@example.com",
+        ] {
+            assert!(validate_email(invalid).is_err(), "{invalid} was accepted");
+        }
+        assert_eq!(validate_email(""), Err("Please enter your email address."));
+        let too_long = format!("{}@example.com", "a".repeat(EMAIL_MAX_LEN));
+        assert_eq!(
+            validate_email(&too_long),
+            Err("This email address is too long.")
+        );
+    }
+
+    #[test]
+    fn validate_username_checks_length_and_characters() {
+        assert!(validate_username("abc").is_ok());
+        assert!(validate_username("jane_doe-42").is_ok());
+        assert!(validate_username(&"a".repeat(USERNAME_MAX_LEN)).is_ok());
+
+        assert!(validate_username("ab").is_err());
+        assert!(validate_username(&"a".repeat(USERNAME_MAX_LEN + 1)).is_err());
+        assert!(validate_username("jane doe").is_err());
+        assert!(validate_username("jané").is_err());
+        assert_eq!(
+            validate_username("_jane"),
+            Err("Usernames must start with a letter or a digit.")
+        );
+    }
+
+    #[test]
+    fn validate_password_checks_length_variety_and_personal_data() {
+        assert!(validate_password("Correct-horse-9", &[]).is_ok());
+        assert!(validate_password("correcthorse9", &["jane", "jane@example.com"]).is_ok());
+
+        assert!(validate_password("Short-1", &[]).is_err());
+        assert!(validate_password(&"Aa1".repeat(50), &[]).is_err());
+        assert_eq!(
+            validate_password("correcthorsebattery", &[]),
+            Err("Passwords must mix at least two of lowercase, uppercase, digits and symbols.")
+        );
+        let personal = Err("Passwords must not contain your username or email address.");
+        assert_eq!(
+            validate_password("my-JaneDoe-password", &["janedoe"]),
+            personal
+        );
+        assert_eq!(
+            validate_password("Jane.smith-2024", &["jane.smith@example.com"]),
+            personal
+        );
+        // Values too short to be meaningful are not looked for.
+        assert!(validate_password("Correct-horse-9", &["co"]).is_ok());
+    }
+
+    #[test]
+    fn sanitize_username_produces_valid_usernames() {
+        assert_eq!(sanitize_username("Jane Doe"), "Jane_Doe");
+        assert_eq!(sanitize_username("jane.doe"), "jane_doe");
+        assert_eq!(sanitize_username("__jane"), "jane");
+        assert_eq!(sanitize_username("Zoë"), "userZo");
+        assert_eq!(sanitize_username("!!!"), "user");
+
+        let long = sanitize_username(&"a".repeat(100));
+        assert_eq!(long.len(), USERNAME_MAX_LEN - 4);
+        for suggestion in ["Jane Doe", "Zoë", "!!!", "__x", &"b".repeat(100)] {
+            let username = sanitize_username(suggestion);
+            assert!(
+                validate_username(&username).is_ok(),
+                "{username} is invalid"
+            );
+        }
+    }
+}